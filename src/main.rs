@@ -1,162 +1,6432 @@
-use std::env;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt::Display;
 use std::fs;
-use std::path::Path;
+use std::io::{IsTerminal, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
 
-use clap::{Arg, Command};
-use pdf_extract::extract_text;
+use clap::{Arg, ArgMatches, Command};
+use clap_complete::Shell;
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+use lopdf::encryption::DecryptionError;
+use md5::{Digest, Md5};
+use sha2::Sha256;
+use regex::Regex;
+use serde_json::json;
+use unicode_bidi::{bidi_class, BidiClass, BidiInfo};
+use pdf_extract::{
+    output_doc, output_doc_page, MediaBox, OutputDev, OutputError, PlainTextOutput,
+    Transform,
+};
 
-fn main() {
-    let matches = Command::new("PDF to Text Converter")
+/// How literal tab characters in the extracted text are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum TabMode {
+    /// Treat tabs like any other whitespace.
+    #[default]
+    Collapse,
+    /// Replace each tab with the given number of spaces.
+    Expand(usize),
+    /// Leave tabs untouched.
+    Keep,
+}
+
+/// How much whitespace cleanup `--whitespace` asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum WhitespaceMode {
+    /// Join lines into running text and collapse every whitespace run to one
+    /// space, keeping paragraph breaks (or line breaks with
+    /// `--collapse-spaces-only`).
+    #[default]
+    Aggressive,
+    /// Only collapse runs of three or more spaces to one; single and double
+    /// spaces, tabs and line breaks are left alone.
+    Conservative,
+    /// Leave whitespace exactly as extracted.
+    Preserve,
+}
+
+/// Line endings used when writing output files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Newline {
+    #[default]
+    Lf,
+    Crlf,
+    /// The platform's convention: CRLF on Windows, LF elsewhere.
+    Native,
+}
+
+/// Options controlling how `process_extracted_text` cleans up the raw text.
+#[derive(Debug, Clone, Default)]
+struct ProcessingOptions {
+    whitespace: WhitespaceMode,
+    tabs: TabMode,
+    /// Normalize whitespace within lines but keep line breaks.
+    collapse_spaces_only: bool,
+    /// Merge letter-spaced runs like "T I T L E" back into words.
+    dekern: bool,
+    /// Replace curly and other typographic quotes with straight ones.
+    normalize_quotes: bool,
+    /// Replace en/em dashes and similar with a plain hyphen.
+    normalize_dashes: bool,
+    /// Leave soft hyphens (U+00AD) in place instead of removing them.
+    keep_soft_hyphens: bool,
+    /// Put right-to-left runs back into logical order with the bidi algorithm.
+    bidi: bool,
+    /// Drop lines that are only a page number, like "5" or "Page 5 of 20".
+    strip_page_numbers: bool,
+    /// Transliterate everything to ASCII ("é" becomes "e", "北京" "Bei Jing").
+    ascii_fold: bool,
+    /// Set when `--max-pages` cut the document short: the pages kept and the
+    /// document's full page count, noted in the banner.
+    truncated: Option<(u32, usize)>,
+    /// Where the text came from, noted in the banner with `--provenance`.
+    provenance: Option<Provenance>,
+    /// Set on each file of a `--max-file-bytes` split: this part's number and
+    /// the number of parts, noted in the banner.
+    part: Option<(usize, usize)>,
+    /// Leave out the metadata banner before the content.
+    no_header: bool,
+    /// Leave out the `=== CONTENT ENDS ===` marker after the content.
+    no_footer: bool,
+    /// Line endings applied by `write_output`; processing itself always uses LF.
+    newline: Newline,
+    /// Permissions set on every file PDFBot writes (Unix only).
+    file_mode: Option<u32>,
+    /// Permissions set on directories PDFBot creates (Unix only).
+    dir_mode: Option<u32>,
+    /// Keep only lines matching this pattern (line breaks are preserved).
+    grep: Option<Regex>,
+    /// Lines of context kept around each `grep` match.
+    grep_context: usize,
+    /// Read each output file back after writing it and check it, for `--verify`.
+    verify: bool,
+}
+
+/// Identifies the exact input a conversion read, for `--provenance`.
+#[derive(Debug, Clone)]
+struct Provenance {
+    sha256: String,
+    size: u64,
+    /// Not known for input that did not come from a file.
+    modified: Option<SystemTime>,
+    converted: SystemTime,
+}
+
+impl Provenance {
+    /// Hashes the file as it is on disk.
+    fn of_file(path: &Path) -> std::io::Result<Provenance> {
+        let mut file = fs::File::open(path)?;
+        let mut hash = Sha256::new();
+        let size = std::io::copy(&mut file, &mut hash)?;
+        Ok(Provenance {
+            sha256: hex(&hash.finalize()),
+            size,
+            modified: file.metadata()?.modified().ok(),
+            converted: SystemTime::now(),
+        })
+    }
+
+    fn of_bytes(bytes: &[u8]) -> Provenance {
+        Provenance {
+            sha256: hex(&Sha256::digest(bytes)),
+            size: bytes.len() as u64,
+            modified: None,
+            converted: SystemTime::now(),
+        }
+    }
+
+    /// The banner lines, one field each.
+    fn banner_lines(&self) -> String {
+        let mut lines = format!("Source SHA-256: {}\nSource size: {} bytes\n", self.sha256, self.size);
+        if let Some(modified) = self.modified {
+            lines.push_str(&format!("Source modified: {}\n", humantime::format_rfc3339_seconds(modified)));
+        }
+        lines.push_str(&format!("Converted: {}\n", humantime::format_rfc3339_seconds(self.converted)));
+        lines
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Reports problems that did not stop a conversion. Warnings go to stderr, or
+/// with `--warn-log` are appended to the log file as tab-separated
+/// `path, page, type, detail` records instead.
+struct Warnings {
+    source: String,
+    log: Option<fs::File>,
+}
+
+impl Warnings {
+    fn warn(&mut self, page: Option<u32>, kind: &str, detail: impl Display) {
+        let detail = detail.to_string();
+        match &mut self.log {
+            Some(log) => {
+                let page = page.map(|p| p.to_string()).unwrap_or_default();
+                let detail = detail.replace(['\t', '\n', '\r'], " ");
+                if let Err(e) = writeln!(log, "{}\t{}\t{}\t{}", self.source, page, kind, detail) {
+                    eprintln!("Warning: could not write to warning log: {}", e);
+                }
+            }
+            None => match page {
+                Some(page) => eprintln!("Warning: page {}: {}", page, detail),
+                None => eprintln!("Warning: {}", detail),
+            },
+        }
+    }
+}
+
+/// An answer to the `--interactive` overwrite prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Overwrite {
+    Yes,
+    Skip,
+    All,
+    Quit,
+}
+
+/// Decides whether an existing output file may be replaced. Without
+/// `--interactive` files are always overwritten; with it the user is asked for
+/// each file until they answer "a" (all) or "q" (quit).
+struct OverwriteGuard {
+    interactive: bool,
+    overwrite_all: bool,
+}
+
+impl OverwriteGuard {
+    /// Returns whether `path` should be written. Quitting exits the process.
+    fn allows(&mut self, path: &Path) -> bool {
+        if !self.interactive || self.overwrite_all || !Path::new(path).exists() {
+            return true;
+        }
+
+        print!("Overwrite {}? [y/N/a/q] ", path.display());
+        let _ = std::io::stdout().flush();
+        let mut reply = String::new();
+        if std::io::stdin().read_line(&mut reply).is_err() {
+            reply.clear();
+        }
+
+        match parse_overwrite_reply(&reply) {
+            Overwrite::Yes => true,
+            Overwrite::All => {
+                self.overwrite_all = true;
+                true
+            }
+            Overwrite::Skip => {
+                println!("Skipped '{}'", path.display());
+                false
+            }
+            Overwrite::Quit => {
+                fail(Failure::Aborted, None, "Aborted; no further files written");
+            }
+        }
+    }
+}
+
+/// Interprets a reply to the overwrite prompt. Anything unrecognised,
+/// including an empty line, means skip.
+fn parse_overwrite_reply(reply: &str) -> Overwrite {
+    match reply.trim().to_ascii_lowercase().as_str() {
+        "y" | "yes" => Overwrite::Yes,
+        "a" | "all" => Overwrite::All,
+        "q" | "quit" => Overwrite::Quit,
+        _ => Overwrite::Skip,
+    }
+}
+
+/// The command line. Options shared by every subcommand are global, so they
+/// may come before or after the subcommand name.
+fn cli() -> Command {
+    Command::new("PDF to Text Converter")
         .version("1.0")
         .about("Converts PDF files to text format for AI processing")
-        .arg(
-            Arg::new("input")
-                .short('i')
-                .long("input")
-                .value_name("FILE")
-                .help("Input PDF file path")
-                .required(true),
+        .subcommand_required(true)
+        .args(shared_args())
+        .subcommand(
+            Command::new("convert")
+                .about("Extract the text of a PDF to a file (the default when no subcommand is given)")
+                .args(processing_args())
+                .args(convert_args())
+                // Accepted from before `info` and `stats` existed, but no longer listed
+                .args(info_args().into_iter().map(|arg| arg.hide(true)))
+                .args(stats_args().into_iter().map(|arg| arg.hide(true))),
         )
-        .arg(
-            Arg::new("output")
-                .short('o')
-                .long("output")
-                .value_name("FILE")
-                .help("Output text file path (optional, defaults to input name with .txt extension)"),
+        .subcommand(
+            Command::new("info")
+                .about("Describe a PDF: page count, metadata and fonts, or one of the reports below")
+                .args(info_args()),
         )
-        .arg(
-            Arg::new("verbose")
-                .short('v')
-                .long("verbose")
-                .help("Enable verbose output")
-                .action(clap::ArgAction::SetTrue),
+        .subcommand(
+            Command::new("stats")
+                .about("Count the words and lines of a PDF and score its text, or time and compare the extraction")
+                .args(processing_args())
+                .args(stats_args()),
         )
-        .get_matches();
+        .subcommand(
+            Command::new("completions")
+                .about("Print a tab-completion script for SHELL to stdout")
+                .arg(
+                    Arg::new("shell")
+                        .value_name("SHELL")
+                        .help("The shell to complete in")
+                        .value_parser(clap::value_parser!(Shell))
+                        .required(true),
+                ),
+        )
+}
+
+/// Inserts `convert` when the command line names no subcommand, so that the
+/// invocations from before subcommands existed (`pdf_bot -i foo.pdf`) still
+/// convert. A subcommand may follow global options, which are skipped along
+/// with their values when looking for it.
+fn with_default_subcommand(mut args: Vec<OsString>) -> Vec<OsString> {
+    let shared = shared_args();
+    let is_option = |arg: &Arg, option: &str| match option.strip_prefix("--") {
+        Some(long) => arg.get_long() == Some(long),
+        None => option.strip_prefix('-').is_some_and(|short| {
+            let mut chars = short.chars();
+            chars.next() == arg.get_short() && chars.next().is_none()
+        }),
+    };
+
+    let mut position = 1;
+    let mut named = false;
+    while let Some(arg) = args.get(position).and_then(|arg| arg.to_str()) {
+        if matches!(arg, "convert" | "info" | "stats" | "completions" | "help" | "-h" | "--help" | "-V" | "--version") {
+            named = true;
+            break;
+        }
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((option, _)) => (option, true),
+            None => (arg, false),
+        };
+        let Some(global) = shared.iter().find(|shared| is_option(shared, option)) else {
+            break;
+        };
+        position += if global.get_action().takes_values() && !inline_value { 2 } else { 1 };
+    }
+    if !named && !args.is_empty() {
+        args.insert(1, OsString::from("convert"));
+    }
+    args
+}
+
+/// Input, page selection and report formatting options, common to all
+/// subcommands.
+fn shared_args() -> Vec<Arg> {
+    vec![
+        Arg::new("input")
+            .short('i')
+            .long("input")
+            .value_parser(clap::value_parser!(PathBuf))
+            .value_name("FILE")
+            .help("Input PDF file path; pipes such as /dev/stdin or <(curl ...) are read into memory first")
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("input-base64")
+            .long("input-base64")
+            .value_name("STRING")
+            .help("Process a base64-encoded PDF given on the command line instead of a file; command lines are length-limited, so keep this to small documents")
+            .conflicts_with("input")
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("max-size")
+            .long("max-size")
+            .value_name("BYTES")
+            .help("Refuse inputs larger than BYTES, including piped input, which is otherwise buffered whatever its size")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .help("Enable verbose output")
+            .action(clap::ArgAction::SetTrue)
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("password")
+            .long("password")
+            .value_name("PASSWORD")
+            .help("Password for encrypted PDFs, tried as both the owner and the user password")
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("repair")
+            .long("repair")
+            .help("When the PDF fails to load or has unreadable objects, rebuild its cross-reference table from the objects in the file and load it again")
+            .action(clap::ArgAction::SetTrue)
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("warn-log")
+            .long("warn-log")
+            .value_parser(clap::value_parser!(PathBuf))
+            .value_name("FILE")
+            .help("Append warnings (including empty/rotated page checks) to FILE as tab-separated path, page, type, detail records instead of printing them to stderr")
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("skip-cover-pages")
+            .long("skip-cover-pages")
+            .value_name("N")
+            .help("Leave out the first N pages (covers, legal notices) before extraction")
+            .value_parser(clap::value_parser!(u32))
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("skip-last")
+            .long("skip-last")
+            .value_name("N")
+            .help("Leave out the last N pages (back matter) before extraction")
+            .value_parser(clap::value_parser!(u32))
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("max-pages")
+            .long("max-pages")
+            .value_name("N")
+            .help("Refuse documents with more than N pages, or with --max-pages-mode truncate, process only the first N")
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("max-pages-mode")
+            .long("max-pages-mode")
+            .value_name("MODE")
+            .help("What --max-pages does with a longer document: refuse it with an error, or truncate it")
+            .value_parser(["refuse", "truncate"])
+            .default_value("refuse")
+            .requires("max-pages")
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Format of reports such as --benchmark: text or json")
+            .value_parser(["text", "json"])
+            .default_value("text")
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("error-format")
+            .long("error-format")
+            .value_name("FORMAT")
+            .help("How fatal errors are reported on stderr: text, or json for one {\"error\", \"message\", \"path\"} object with a stable error code")
+            .value_parser(["text", "json"])
+            .default_value("text")
+            .help_heading("Shared options")
+            .global(true),
+        Arg::new("pretty")
+            .long("pretty")
+            .help("Indent JSON output (--layout-json, --format json) for reading instead of printing it compactly")
+            .action(clap::ArgAction::SetTrue)
+            .help_heading("Shared options")
+            .global(true),
+    ]
+}
+
+/// How extracted text is cleaned up, for `convert` and `stats`.
+fn processing_args() -> Vec<Arg> {
+    vec![
+        Arg::new("whitespace")
+            .long("whitespace")
+            .value_name("LEVEL")
+            .help("Whitespace cleanup: aggressive (join lines, collapse all runs), conservative (only collapse 3+ spaces to one), or none; --tabs and --collapse-spaces-only only apply to aggressive and are rejected with the others")
+            .value_parser(parse_whitespace_mode)
+            .default_value("aggressive"),
+        Arg::new("tabs")
+            .long("tabs")
+            .value_name("MODE")
+            .help("How to handle tab characters: collapse, keep, or expand[=N] (N spaces, default 4); needs --whitespace aggressive")
+            .value_parser(parse_tab_mode)
+            .default_value("collapse"),
+        Arg::new("newline")
+            .long("newline")
+            .value_name("STYLE")
+            .help("Line endings for output files: lf, crlf, or native (the OS default)")
+            .value_parser(parse_newline)
+            .default_value("lf"),
+        Arg::new("collapse-spaces-only")
+            .long("collapse-spaces-only")
+            .help("Collapse runs of spaces within lines but keep line breaks instead of joining lines; needs --whitespace aggressive")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("dekern")
+            .long("dekern")
+            .help("Merge letter-spaced text such as 'T I T L E' back into words")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("normalize-quotes")
+            .long("normalize-quotes")
+            .help("Replace curly quotes (‘ ’ “ ” etc.) with straight ' and \"")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("normalize-dashes")
+            .long("normalize-dashes")
+            .help("Replace en/em dashes and similar characters with '-'")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("bidi")
+            .long("bidi")
+            .help("Reorder lines containing right-to-left scripts (Arabic, Hebrew) into logical order")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("strip-page-numbers")
+            .long("strip-page-numbers")
+            .help("Remove lines that are just a page number, such as \"Page 5 of 20\" or a bare \"5\" between its page's paragraphs")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("ascii-fold")
+            .long("ascii-fold")
+            .help("Transliterate the text to plain ASCII as the last cleanup step (é to e, ß to ss, other scripts romanized)")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("keep-soft-hyphens")
+            .long("keep-soft-hyphens")
+            .help("Keep the invisible soft hyphens (U+00AD) some PDFs put inside words, which are otherwise removed")
+            .action(clap::ArgAction::SetTrue),
+    ]
+}
+
+/// Output, extraction mode and splitting options of `convert`.
+fn convert_args() -> Vec<Arg> {
+    vec![
+        Arg::new("output")
+            .short('o')
+            .long("output")
+            .value_parser(clap::value_parser!(PathBuf))
+            .value_name("FILE")
+            .help("Output text file path (optional, defaults to input name with .txt extension)"),
+        Arg::new("interactive")
+            .long("interactive")
+            .help("Ask before overwriting existing output files (y = overwrite, N = skip, a = all, q = quit)")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("template-file")
+            .long("template-file")
+            .value_parser(clap::value_parser!(PathBuf))
+            .value_name("FILE")
+            .help("Render the output through a template instead of the banner; {{content}}, {{source}}, {{pages}} and {{title}} are replaced")
+            .conflicts_with_all(["split-by-heading", "pages-per-file"]),
+        Arg::new("provenance")
+            .long("provenance")
+            .help("Record the input's SHA-256, size and modification time and the conversion time in the banner")
+            .conflicts_with_all(["no-header", "page"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("no-header")
+            .long("no-header")
+            .help("Omit the metadata banner at the top of the output")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("no-footer")
+            .long("no-footer")
+            .help("Omit the '=== CONTENT ENDS ===' marker at the end of the output")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("file-mode")
+            .long("file-mode")
+            .value_name("MODE")
+            .help("Octal permissions for written files, e.g. 600, regardless of the umask (Unix only)")
+            .value_parser(parse_mode),
+        Arg::new("dir-mode")
+            .long("dir-mode")
+            .value_name("MODE")
+            .help("Octal permissions for directories PDFBot creates, e.g. 700 (Unix only)")
+            .value_parser(parse_mode),
+        Arg::new("grep")
+            .long("grep")
+            .value_name("PATTERN")
+            .help("Keep only lines matching the regex PATTERN, keeping line breaks at every --whitespace level; exits with status 2 when nothing matches")
+            .value_parser(|value: &str| Regex::new(value).map_err(|e| e.to_string())),
+        Arg::new("context")
+            .long("context")
+            .value_name("N")
+            .help("With --grep, also keep N lines before and after each match")
+            .value_parser(clap::value_parser!(usize))
+            .requires("grep")
+            .default_value("0"),
+        Arg::new("keep-raw")
+            .long("keep-raw")
+            .help("Also write the unprocessed extraction next to the output as NAME.raw.txt")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("cache-dir")
+            .long("cache-dir")
+            .value_parser(clap::value_parser!(PathBuf))
+            .value_name("DIR")
+            .help("Keep the raw extraction of each input in DIR, keyed by the input's SHA-256, and reuse it on later runs so only the cleanup runs again"),
+        Arg::new("extract-annotations")
+            .long("extract-annotations")
+            .help("Append text notes, highlights and popup comments as a 'Comments:' section")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("use-struct-tree")
+            .long("use-struct-tree")
+            .help("For tagged PDFs, extract text in the logical order of the structure tree (falls back with a warning for untagged files)")
+            .conflicts_with_all(["destination", "page", "flatten-annotations", "split-by-heading", "pages-per-file"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("use-page-labels")
+            .long("use-page-labels")
+            .help("Refer to pages by the document's own page labels (i, ii, 1, A-1, ...) instead of physical page numbers in page markers")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("flatten-annotations")
+            .long("flatten-annotations")
+            .help("Insert annotation text inline after the page line it sits on, instead of a separate Comments section")
+            .conflicts_with_all(["extract-annotations", "destination", "page", "split-by-heading", "pages-per-file"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("emphasis-markdown")
+            .long("emphasis-markdown")
+            .help("Mark text set larger than the body font as Markdown headings and bold text as **bold**")
+            .conflicts_with_all(["use-struct-tree", "flatten-annotations", "destination", "page", "split-by-heading", "pages-per-file"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("crop")
+            .long("crop")
+            .value_name("[PAGE:]X,Y,W,H")
+            .help("Keep only text inside the box with lower-left corner X,Y and size W by H in PDF units (1/72 inch); PAGE: limits a box to that page, replacing the boxes without a page there; repeat for several boxes")
+            .value_parser(parse_crop)
+            .action(clap::ArgAction::Append)
+            .conflicts_with_all(["use-struct-tree", "flatten-annotations", "emphasis-markdown", "separate-footnotes", "mark-super-sub", "destination", "page", "split-by-heading", "pages-per-file"]),
+        Arg::new("mark-super-sub")
+            .long("mark-super-sub")
+            .help("Mark raised small text as ^{...} and lowered small text as _{...}, keeping exponents, reference numbers and chemical formulas apart from the text around them")
+            .conflicts_with_all(["use-struct-tree", "flatten-annotations", "emphasis-markdown", "separate-footnotes", "destination", "page", "split-by-heading", "pages-per-file"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("separate-footnotes")
+            .long("separate-footnotes")
+            .help("Move small-print notes at the bottom of pages out of the body text into a closing Footnotes section labelled with their pages")
+            .conflicts_with_all(["use-struct-tree", "flatten-annotations", "emphasis-markdown", "destination", "page", "split-by-heading", "pages-per-file"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("extract-attachments")
+            .long("extract-attachments")
+            .value_parser(clap::value_parser!(PathBuf))
+            .value_name("DIR")
+            .help("Save embedded file attachments into DIR and list them as [ATTACHMENT: name] markers in the output"),
+        Arg::new("trace-positions")
+            .long("trace-positions")
+            .value_parser(clap::value_parser!(PathBuf))
+            .value_name("FILE")
+            .help("Debug: dump every text run with its coordinates to stderr or FILE")
+            .num_args(0..=1)
+            .default_missing_value("-")
+            .hide(true),
+        Arg::new("layout-json")
+            .long("layout-json")
+            .help("Print each text run of the --page page with its font, size and bounding box as JSON, then exit")
+            .requires("page")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("layout-csv")
+            .long("layout-csv")
+            .value_parser(clap::value_parser!(PathBuf))
+            .value_name("FILE")
+            .help("Write every text line as CSV (page, x, y, width, height, text) to FILE or - for stdout, then exit; coordinates are PDF points from the bottom-left of the page, y on the baseline")
+            .conflicts_with("layout-json"),
+        Arg::new("page")
+            .long("page")
+            .value_name("N")
+            .help("Extract only page N (1-based) without the banner and footer; also the page --layout-json describes")
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .conflicts_with_all(["destination", "split-by-heading", "pages-per-file"]),
+        Arg::new("stdout")
+            .long("stdout")
+            .help("Print the converted text to stdout instead of writing a file")
+            .conflicts_with_all(["output", "split-by-heading", "pages-per-file", "interactive"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("verify")
+            .long("verify")
+            .help("Read every output file back after writing it and fail if it is short, differs, is not UTF-8, or has the banner or footer when it should not (or lacks them when it should)")
+            .conflicts_with("stdout")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("quality-score")
+            .long("quality-score")
+            .help("Report a 0-100 heuristic score of how usable the extracted text looks")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("split-by-heading")
+            .long("split-by-heading")
+            .help("Write one file per top-level outline entry (name_ch01.txt, ...)")
+            .conflicts_with("destination")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("expand-portfolio")
+            .long("expand-portfolio")
+            .help("For PDF portfolios, convert each embedded PDF to its own file (name_doc01.txt, ...) instead of the cover sheet")
+            .conflicts_with_all(["split-by-heading", "pages-per-file", "page", "destination", "stdout"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("pages-per-file")
+            .long("pages-per-file")
+            .value_name("N")
+            .help("Write every N pages to their own file (name_part01.txt, ...); the last file may be shorter")
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .conflicts_with_all(["destination", "split-by-heading"]),
+        Arg::new("max-file-bytes")
+            .long("max-file-bytes")
+            .value_name("N")
+            .help("Split output larger than N bytes into numbered part files (name_part01.txt, ...), breaking at line or word boundaries")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .conflicts_with_all(["stdout", "template-file", "split-by-heading", "pages-per-file"]),
+        Arg::new("destination")
+            .long("destination")
+            .value_name("NAME")
+            .help("Extract from the page a named destination points to through the end of the document"),
+        Arg::new("destination-only")
+            .long("destination-only")
+            .help("With --destination, extract only the destination's page")
+            .requires("destination")
+            .action(clap::ArgAction::SetTrue),
+    ]
+}
+
+/// The reports of `info`.
+fn info_args() -> Vec<Arg> {
+    vec![
+        Arg::new("count-pages")
+            .long("count-pages")
+            .help("Print the number of pages in the PDF and exit without extracting text")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("xmp")
+            .long("xmp")
+            .help("Report PDF/A conformance and Dublin Core fields from the XMP metadata and exit")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("report-fonts")
+            .long("report-fonts")
+            .help("List the document's fonts with their type, encoding, and whether they are embedded and have a ToUnicode map, then exit")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("scanned-pages")
+            .long("scanned-pages")
+            .help("Report which pages look scanned (a large image but no text) and which have text, then exit")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("scan-js")
+            .long("scan-js")
+            .help("Report where the PDF contains JavaScript actions and exit without extracting text")
+            .action(clap::ArgAction::SetTrue),
+    ]
+}
+
+/// The reports of `stats`.
+fn stats_args() -> Vec<Arg> {
+    vec![
+        Arg::new("benchmark")
+            .long("benchmark")
+            .value_name("RUNS")
+            .help("Time extraction and processing over RUNS runs (default 10) and report mean/median/p95; uses a bundled sample without --input")
+            .num_args(0..=1)
+            .default_missing_value("10")
+            .value_parser(clap::value_parser!(u32).range(1..)),
+        Arg::new("compare-backends")
+            .long("compare-backends")
+            .help("Run both the default and the position-based extraction and report how their output and timing differ, then exit")
+            .action(clap::ArgAction::SetTrue),
+    ]
+}
+
+fn main() {
+    let args = with_default_subcommand(std::env::args_os().collect());
+    let matches = match cli().try_get_matches_from(&args) {
+        Ok(matches) => matches,
+        // Help and version output are not failures
+        Err(e) if e.use_stderr() && json_errors_requested(&args) => {
+            JSON_ERRORS.store(true, Ordering::Relaxed);
+            fail(Failure::Usage, None, e.render().to_string().trim_end());
+        }
+        Err(e) => e.exit(),
+    };
+    if let Some((_, matches)) = matches.subcommand() {
+        let json = matches.get_one::<String>("error-format").is_some_and(|format| format == "json");
+        JSON_ERRORS.store(json, Ordering::Relaxed);
+    }
 
-    let input_path = matches.get_one::<String>("input").unwrap();
+    match matches.subcommand() {
+        Some(("info", matches)) => run_info(matches),
+        Some(("stats", matches)) => run_stats(matches),
+        Some(("completions", matches)) => {
+            let shell = *matches.get_one::<Shell>("shell").unwrap();
+            clap_complete::generate(shell, &mut cli(), env!("CARGO_BIN_NAME"), &mut std::io::stdout());
+        }
+        Some((_, matches)) => run_convert(matches),
+        None => unreachable!("a subcommand is required"),
+    }
+}
+
+fn run_convert(matches: &ArgMatches) {
     let verbose = matches.get_flag("verbose");
+    let grep = matches.get_one::<Regex>("grep").cloned();
+    let single_page = matches.get_one::<u32>("page").copied().filter(|_| !matches.get_flag("layout-json"));
+    let processing = processing_options(matches);
+    let mut options = ProcessingOptions {
+        collapse_spaces_only: processing.collapse_spaces_only || matches.get_flag("emphasis-markdown"),
+        no_header: matches.get_flag("no-header") || single_page.is_some(),
+        no_footer: matches.get_flag("no-footer") || single_page.is_some(),
+        file_mode: matches.get_one::<u32>("file-mode").copied(),
+        dir_mode: matches.get_one::<u32>("dir-mode").copied(),
+        grep,
+        grep_context: *matches.get_one::<usize>("context").unwrap(),
+        verify: matches.get_flag("verify"),
+        ..processing
+    };
 
-    // Check if input file exists
-    if !Path::new(input_path).exists() {
-        eprintln!("Error: Input file '{}' does not exist", input_path);
-        process::exit(1);
+    let mut overwrite = OverwriteGuard {
+        interactive: matches.get_flag("interactive"),
+        overwrite_all: false,
+    };
+    if overwrite.interactive && !std::io::stdout().is_terminal() {
+        fail(Failure::InvalidOptions, None, "Error: --interactive needs a terminal to prompt on");
+    }
+
+    if cfg!(not(unix)) && (options.file_mode.is_some() || options.dir_mode.is_some()) {
+        eprintln!("Warning: --file-mode and --dir-mode only take effect on Unix");
+    }
+
+    let template = matches.get_one::<PathBuf>("template-file").map(|path| {
+        let template = fs::read_to_string(path).unwrap_or_else(|e| {
+            fail(Failure::Template, Some(&path.display().to_string()), format!("Error reading template '{}': {}", path.display(), e));
+        });
+        if !template_placeholder().captures_iter(&template).any(|c| &c[1] == "content") {
+            fail(Failure::Template, Some(&path.display().to_string()), format!("Error: template '{}' has no {{{{content}}}} placeholder", path.display()));
+        }
+        template
+    });
+
+    let pretty = matches.get_flag("pretty");
+    if benchmark_sample(matches, &options) {
+        return;
+    }
+
+    let Input {
+        mut doc,
+        source,
+        path: input_path,
+        mut warnings,
+        provenance,
+    } = open_input(matches, matches.get_flag("provenance") || matches.contains_id("cache-dir"));
+    let input_sha256 = provenance.as_ref().map(|provenance| provenance.sha256.clone());
+    options.provenance = provenance.filter(|_| matches.get_flag("provenance"));
+
+    if matches.get_flag("count-pages") {
+        println!("{}", count_pages(&doc));
+        return;
+    }
+
+    options.truncated = limit_pages(&mut doc, matches, &source, verbose);
+    let cache = matches.get_one::<PathBuf>("cache-dir").zip(input_sha256).map(|(dir, sha256)| {
+        let skip_first = matches.get_one::<u32>("skip-cover-pages").copied().unwrap_or(0);
+        let skip_last = matches.get_one::<u32>("skip-last").copied().unwrap_or(0);
+        let kept = options.truncated.map_or(0, |(kept, _)| kept);
+        ExtractionCache::new(dir, sha256, (skip_first, skip_last, kept))
+    });
+
+    if print_stats_report(&doc, &source, matches, &options) || print_info_report(&doc, &source, matches, &mut warnings) {
+        return;
+    }
+
+    if matches.get_flag("layout-json") {
+        let page = *matches.get_one::<u32>("page").unwrap();
+        let layout = layout_json(&doc, page).map(|layout| render_json(&layout, pretty) + "\n");
+        let written = layout.and_then(|layout| match matches.get_one::<PathBuf>("output") {
+            Some(path) => write_file(path, layout.as_bytes(), &options).map_err(Into::into),
+            None => std::io::stdout().write_all(layout.as_bytes()).map_err(Into::into),
+        });
+        if let Err(e) = written {
+            fail(Failure::Extraction, Some(&source), format!("Error describing page layout: {}", e));
+        }
+        return;
+    }
+
+    if let Some(target) = matches.get_one::<PathBuf>("layout-csv") {
+        let csv = layout_csv(&doc);
+        let written = csv.and_then(|csv| {
+            if target.as_os_str() == "-" {
+                std::io::stdout().write_all(csv.as_bytes()).map_err(Into::into)
+            } else {
+                write_file(target, csv.as_bytes(), &options).map_err(Into::into)
+            }
+        });
+        if let Err(e) = written {
+            fail(Failure::Output, Some(&source), format!("Error writing layout CSV: {}", e));
+        }
+        return;
     }
 
     // Determine output path
-    let output_path = match matches.get_one::<String>("output") {
+    let output_path = match matches.get_one::<PathBuf>("output") {
         Some(path) => path.clone(),
-        None => {
-            let input_path_obj = Path::new(input_path);
-            let stem = input_path_obj.file_stem().unwrap().to_str().unwrap();
-            format!("{}.txt", stem)
-        }
+        None => match &input_path {
+            // A pipe's name, like /dev/fd/63, says nothing about the document
+            Some(input_path) if !is_stream(input_path) => default_output_path(input_path).unwrap_or_else(|e| {
+                fail(Failure::InvalidOptions, Some(&source), format!("Error: {}", e));
+            }),
+            _ => PathBuf::from("output.txt"),
+        },
     };
 
     if verbose {
-        println!("Input file: {}", input_path);
-        println!("Output file: {}", output_path);
+        println!("Input file: {}", source);
+        println!("Output file: {}", output_path.display());
         println!("Starting PDF text extraction...");
     }
 
+    if let Some(target) = matches.get_one::<PathBuf>("trace-positions") {
+        let traced = if target.as_os_str() == "-" {
+            trace_positions(&doc, &mut std::io::stderr().lock())
+        } else {
+            fs::File::create(target)
+                .map_err(Into::into)
+                .and_then(|mut file| trace_positions(&doc, &mut file))
+        };
+        if let Err(e) = traced {
+            warnings.warn(None, "trace_failed", format!("could not trace text positions: {}", e));
+        }
+    }
+
+    if matches.get_flag("split-by-heading") {
+        match split_by_heading(&doc, &output_path, &options, &mut overwrite) {
+            Ok(Some(paths)) => {
+                if verbose {
+                    for path in &paths {
+                        println!("Wrote chapter file: {}", path.display());
+                    }
+                }
+                println!("✅ Successfully split '{}' into {} chapter files", source, paths.len());
+                return;
+            }
+            Ok(None) => {
+                warnings.warn(
+                    None,
+                    "no_outline",
+                    format!("'{}' has no outline to split by, writing a single file", source),
+                );
+            }
+            Err(e) => {
+                fail(Failure::Output, Some(&source), format!("Error splitting PDF by heading: {}", e));
+            }
+        }
+    }
+
+    if let Some(&pages_per_file) = matches.get_one::<u32>("pages-per-file") {
+        match split_by_pages(&doc, &output_path, pages_per_file, &options, &mut overwrite) {
+            Ok(paths) => {
+                if verbose {
+                    for path in &paths {
+                        println!("Wrote part file: {}", path.display());
+                    }
+                }
+                println!("✅ Successfully split '{}' into {} part files", source, paths.len());
+                return;
+            }
+            Err(e) => {
+                fail(Failure::Output, Some(&source), format!("Error splitting PDF by pages: {}", e));
+            }
+        }
+    }
+
+    if matches.get_flag("expand-portfolio") {
+        if !is_portfolio(&doc) {
+            fail(Failure::NotPortfolio, Some(&source), format!("Error: '{}' is not a PDF portfolio (it has no /Collection)", source));
+        }
+        match expand_portfolio(&doc, &output_path, &options, &mut overwrite, &mut warnings) {
+            Ok(written) => {
+                if verbose {
+                    for (name, path) in &written {
+                        println!("Wrote '{}' to {}", name, path.display());
+                    }
+                }
+                println!("✅ Successfully expanded portfolio '{}' into {} document files", source, written.len());
+                return;
+            }
+            Err(e) => {
+                fail(Failure::Output, Some(&source), format!("Error expanding PDF portfolio: {}", e));
+            }
+        }
+    }
+    if is_portfolio(&doc) {
+        warnings.warn(
+            None,
+            "portfolio",
+            "document is a PDF portfolio; only its cover sheet is extracted, use --expand-portfolio for the embedded documents",
+        );
+    }
+
+    let crop: Vec<CropRegion> = matches.get_many::<CropRegion>("crop").map_or_else(Vec::new, |regions| regions.copied().collect());
+    let labels = if matches.get_flag("use-page-labels") {
+        page_labels(&doc, matches.get_one::<u32>("skip-cover-pages").copied().unwrap_or(0))
+    } else {
+        HashMap::new()
+    };
+
     // Extract text from PDF
-    match extract_text_from_pdf(input_path) {
+    let extraction = match (single_page, matches.get_one::<String>("destination")) {
+        _ if matches.get_flag("use-struct-tree") => match extract_struct_tree(&doc) {
+            Ok(Some(text)) if !text.trim().is_empty() => Ok(text),
+            Ok(tree) => {
+                let reason = if tree.is_none() { "is not tagged" } else { "has no text in its structure tree" };
+                warnings.warn(
+                    None,
+                    "no_struct_tree",
+                    format!("'{}' {}, using the default extraction", source, reason),
+                );
+                extract_document_text(&doc).map_err(Into::into)
+            }
+            Err(e) => Err(e.into()),
+        },
+        _ if matches.get_flag("emphasis-markdown") => emphasis_markdown(&doc).map_err(Into::into),
+        _ if matches.get_flag("separate-footnotes") => separate_footnotes(&doc, &labels).map_err(Into::into),
+        _ if matches.get_flag("mark-super-sub") => mark_super_sub(&doc).map_err(Into::into),
+        _ if !crop.is_empty() => crop_text(&doc, &crop).map_err(Into::into),
+        _ if matches.get_flag("flatten-annotations") => read_annotations(&doc)
+            .and_then(|annotations| flatten_annotations(&doc, &annotations).map_err(Into::into)),
+        (Some(page), _) => extract_single_page(&doc, page),
+        (None, Some(name)) => extract_from_destination(&doc, name, matches.get_flag("destination-only")),
+        (None, None) => match &cache {
+            Some(cache) => extract_with_cache(&doc, verbose, cache, &options, &mut warnings).map_err(Into::into),
+            None => extract_document_text(&doc).map_err(Into::into),
+        },
+    };
+
+    match extraction {
         Ok(text) => {
             if verbose {
                 println!("Successfully extracted {} characters", text.len());
             }
+            if matches.get_flag("quality-score") {
+                println!("📊 Extraction quality score: {}/100", quality_score(&text));
+            }
+            check_document(&doc, &text, &mut warnings);
+            if matches.get_flag("keep-raw") {
+                let raw_path = numbered_output_path(&output_path, ".raw");
+                if overwrite.allows(&raw_path) {
+                    match write_file(&raw_path, text.as_bytes(), &options) {
+                        Ok(_) if verbose => println!("Wrote raw extraction: {}", raw_path.display()),
+                        Ok(_) => {}
+                        Err(e) => warnings.warn(
+                            None,
+                            "raw_failed",
+                            format!("could not write '{}': {}", raw_path.display(), e),
+                        ),
+                    }
+                }
+            }
 
             // Process and clean the text
-            let processed_text = process_extracted_text(&text);
+            let mut content = clean_extracted_text(&text, &options);
+            // Only the document text counts as a match, not the sections added below
+            let matched = options
+                .grep
+                .as_ref()
+                .is_none_or(|pattern| content.lines().any(|line| pattern.is_match(line)));
+            if matches.get_flag("extract-annotations") {
+                match read_annotations(&doc) {
+                    Ok(annotations) if annotations.is_empty() => {
+                        if verbose {
+                            println!("No annotations found");
+                        }
+                    }
+                    Ok(annotations) => {
+                        if verbose {
+                            println!("Found {} annotations", annotations.len());
+                        }
+                        content.push_str("\n\n");
+                        content.push_str(&format_comments(&annotations, &labels));
+                    }
+                    Err(e) => warnings.warn(None, "annotations_failed", format!("could not read annotations: {}", e)),
+                }
+            }
+            if let Some(dir) = matches.get_one::<PathBuf>("extract-attachments") {
+                let saved = read_attachments(&doc)
+                    .map_err(|e| e.to_string())
+                    .and_then(|attachments| save_attachments(&attachments, dir, &options).map_err(|e| e.to_string()));
+                match saved {
+                    Ok(names) => {
+                        if verbose {
+                            println!("Saved {} attachments to {}", names.len(), dir.display());
+                        }
+                        for name in names {
+                            content.push_str(&format!("\n\n[ATTACHMENT: {}]", name));
+                        }
+                    }
+                    Err(e) => warnings.warn(None, "attachments_failed", format!("could not extract attachments: {}", e)),
+                }
+            }
+            let processed_text = match &template {
+                Some(template) => {
+                    let pages = count_pages(&doc).to_string();
+                    let title = document_title(&doc).unwrap_or_default();
+                    let fields = [
+                        ("content", content.as_str()),
+                        ("source", source.as_str()),
+                        ("pages", pages.as_str()),
+                        ("title", title.as_str()),
+                    ];
+                    render_template(template, &fields)
+                }
+                None => wrap_content(&content, &options),
+            };
+            if matches.get_flag("stdout") {
+                let written = std::io::stdout().write_all(with_line_endings(&processed_text, options.newline).as_bytes());
+                if let Err(e) = written {
+                    fail(Failure::Output, Some(&source), format!("Error writing to stdout: {}", e));
+                }
+                process::exit(if matched { 0 } else { 2 });
+            }
+            if let Some(&max_bytes) = matches.get_one::<u64>("max-file-bytes") {
+                if with_line_endings(&processed_text, options.newline).len() as u64 > max_bytes {
+                    match write_size_limited_parts(&content, &output_path, max_bytes as usize, &options, &mut overwrite) {
+                        Ok(paths) => {
+                            if verbose {
+                                for path in &paths {
+                                    println!("Wrote part file: {}", path.display());
+                                }
+                            }
+                            println!("✅ Successfully converted '{}' into {} part files of at most {} bytes", source, paths.len(), max_bytes);
+                            process::exit(if matched { 0 } else { 2 });
+                        }
+                        Err(e) => {
+                            fail(Failure::Output, Some(&source), format!("Error writing output parts: {}", e));
+                        }
+                    }
+                }
+            }
+            if !overwrite.allows(&output_path) {
+                return;
+            }
 
             // Write to output file
-            match fs::write(&output_path, processed_text) {
+            // A template takes the place of the banner and footer
+            let written_options = match template {
+                Some(_) => ProcessingOptions { no_header: true, no_footer: true, ..options.clone() },
+                None => options.clone(),
+            };
+            match write_output(&output_path, &processed_text, &written_options) {
                 Ok(_) => {
-                    println!("✅ Successfully converted '{}' to '{}'", input_path, output_path);
+                    println!("✅ Successfully converted '{}' to '{}'", source, output_path.display());
                     if verbose {
                         println!("Text extraction complete!");
                     }
+                    if !matched {
+                        println!("No lines matched the --grep pattern");
+                        process::exit(2);
+                    }
                 }
                 Err(e) => {
-                    eprintln!("Error writing to output file: {}", e);
-                    process::exit(1);
+                    fail(Failure::Output, Some(&source), format!("Error writing to output file: {}", e));
                 }
             }
         }
         Err(e) => {
-            eprintln!("Error extracting text from PDF: {}", e);
-            process::exit(1);
+            fail(Failure::Extraction, Some(&source), format!("Error extracting text from PDF: {}", e));
         }
     }
 }
 
-fn extract_text_from_pdf(file_path: &str) -> Result<String, Box<dyn std::error::Error>> {
-    // Extract text using pdf-extract directly with the file path
-    let text = extract_text(file_path)?;
-    
-    Ok(text)
+fn run_info(matches: &ArgMatches) {
+    let verbose = matches.get_flag("verbose");
+    let Input { mut doc, source, mut warnings, .. } = open_input(matches, false);
+
+    if matches.get_flag("count-pages") {
+        println!("{}", count_pages(&doc));
+        return;
+    }
+
+    limit_pages(&mut doc, matches, &source, verbose);
+    if !print_info_report(&doc, &source, matches, &mut warnings) {
+        print_info(&doc, &source, json_format(matches));
+    }
 }
 
-fn process_extracted_text(raw_text: &str) -> String {
-    let mut processed = String::new();
-    let mut prev_char = ' ';
-    
-    for line in raw_text.lines() {
-        let trimmed = line.trim();
-        
-        // Skip empty lines but preserve paragraph breaks
-        if trimmed.is_empty() {
-            if !processed.ends_with("\n\n") && !processed.is_empty() {
-                processed.push('\n');
+fn run_stats(matches: &ArgMatches) {
+    let verbose = matches.get_flag("verbose");
+    let options = processing_options(matches);
+    if benchmark_sample(matches, &options) {
+        return;
+    }
+
+    let Input { mut doc, source, .. } = open_input(matches, false);
+    limit_pages(&mut doc, matches, &source, verbose);
+    if !print_stats_report(&doc, &source, matches, &options) {
+        print_stats(&doc, &source, &options, json_format(matches));
+    }
+}
+
+/// Rejects `--tabs` and `--collapse-spaces-only` given with a `--whitespace`
+/// level they do not apply to, rather than ignoring them.
+fn check_whitespace_options(matches: &ArgMatches) -> Result<(), String> {
+    let whitespace = matches.get_one::<WhitespaceMode>("whitespace").unwrap();
+    if *whitespace == WhitespaceMode::Aggressive {
+        return Ok(());
+    }
+    let level = matches.get_raw("whitespace").into_iter().flatten().next_back().map_or_else(String::new, |raw| raw.to_string_lossy().into_owned());
+    for (id, flag) in [("tabs", "--tabs"), ("collapse-spaces-only", "--collapse-spaces-only")] {
+        if matches.value_source(id) == Some(clap::parser::ValueSource::CommandLine) {
+            return Err(format!("{} has no effect with --whitespace {}; it only applies to --whitespace aggressive", flag, level));
+        }
+    }
+    Ok(())
+}
+
+/// The text cleanup options shared by `convert` and `stats`, with everything
+/// else at its default.
+fn processing_options(matches: &ArgMatches) -> ProcessingOptions {
+    if let Err(e) = check_whitespace_options(matches) {
+        fail(Failure::Usage, None, format!("Error: {}", e));
+    }
+    ProcessingOptions {
+        whitespace: *matches.get_one::<WhitespaceMode>("whitespace").unwrap(),
+        tabs: *matches.get_one::<TabMode>("tabs").unwrap(),
+        collapse_spaces_only: matches.get_flag("collapse-spaces-only"),
+        dekern: matches.get_flag("dekern"),
+        normalize_quotes: matches.get_flag("normalize-quotes"),
+        normalize_dashes: matches.get_flag("normalize-dashes"),
+        keep_soft_hyphens: matches.get_flag("keep-soft-hyphens"),
+        bidi: matches.get_flag("bidi"),
+        strip_page_numbers: matches.get_flag("strip-page-numbers"),
+        ascii_fold: matches.get_flag("ascii-fold"),
+        newline: *matches.get_one::<Newline>("newline").unwrap(),
+        ..ProcessingOptions::default()
+    }
+}
+
+/// Whether reports should be JSON, and if so whether pretty-printed, from
+/// `--format` and `--pretty`.
+fn json_format(matches: &ArgMatches) -> Option<bool> {
+    let json = matches.get_one::<String>("format").is_some_and(|format| format == "json");
+    json.then_some(matches.get_flag("pretty"))
+}
+
+/// Runs `--benchmark` on the bundled sample when no input was given, the one
+/// case that needs no input. Returns whether it ran.
+fn benchmark_sample(matches: &ArgMatches, options: &ProcessingOptions) -> bool {
+    let runs = matches.get_one::<u32>("benchmark");
+    let has_input = matches.contains_id("input") || matches.contains_id("input-base64");
+    match runs {
+        Some(&runs) if !has_input => {
+            let sample = build_pdf(&[BENCHMARK_SAMPLE_PAGE; 5]);
+            print_benchmark(&sample, "bundled sample", runs as usize, options, json_format(matches));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// An opened input document and where it came from.
+struct Input {
+    doc: Document,
+    /// The input as named in messages: its path, or "base64 input".
+    source: String,
+    path: Option<PathBuf>,
+    warnings: Warnings,
+    /// Set when `provenance` was asked for.
+    provenance: Option<Provenance>,
+}
+
+/// Opens the document named by `--input` or `--input-base64`, decrypting it
+/// with `--password`, and sets up warnings for it. Exits with an error message
+/// when there is no input or it cannot be opened.
+fn open_input(matches: &ArgMatches, provenance: bool) -> Input {
+    let verbose = matches.get_flag("verbose");
+    let input_path = matches.get_one::<PathBuf>("input");
+    let input_base64 = matches.get_one::<String>("input-base64");
+    if input_path.is_none() && input_base64.is_none() {
+        fail(Failure::Usage, None, "Error: no input given; pass a PDF with --input FILE or --input-base64 STRING");
+    }
+
+    // Check if input file exists
+    if let Some(input_path) = input_path {
+        if !input_path.exists() {
+            fail(Failure::InputNotFound, Some(&input_path.display().to_string()), format!("Error: Input file '{}' does not exist", input_path.display()));
+        }
+    }
+
+    let warn_log = matches.get_one::<PathBuf>("warn-log").map(|path| {
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| {
+                fail(Failure::Output, Some(&path.display().to_string()), format!("Error opening warning log '{}': {}", path.display(), e));
+            })
+    });
+    let source = match input_path {
+        Some(path) => path.display().to_string(),
+        None => "base64 input".to_string(),
+    };
+    let mut warnings = Warnings {
+        source: source.clone(),
+        log: warn_log,
+    };
+
+    let password = matches.get_one::<String>("password").map(String::as_str);
+    let max_size = matches.get_one::<u64>("max-size").copied();
+    let too_large = |size: u64| max_size.is_some_and(|max| size > max);
+    let mut input_provenance = None;
+    let mut input_bytes = None;
+    let loaded = match (input_path, input_base64) {
+        (Some(path), _) if is_stream(path) => {
+            let bytes = read_stream(path, max_size).unwrap_or_else(|e| {
+                fail(Failure::InputUnreadable, Some(&source), format!("Error reading '{}': {}", source, e));
+            });
+            if verbose {
+                println!("Read {} bytes from the stream '{}'", bytes.len(), source);
             }
-            continue;
+            if provenance {
+                input_provenance = Some(Provenance::of_bytes(&bytes));
+            }
+            let loaded = Document::load_mem(&bytes).and_then(|doc| unlock_document(doc, password));
+            input_bytes = Some(bytes);
+            loaded
         }
-        
-        // Add line with proper spacing
-        if !processed.is_empty() && !processed.ends_with('\n') {
-            // Check if we need a space between words that got split across lines
-            let last_char = processed.chars().last().unwrap_or(' ');
-            let first_char = trimmed.chars().next().unwrap_or(' ');
-            
-            if last_char.is_alphanumeric() && first_char.is_alphanumeric() {
-                processed.push(' ');
-            } else if !last_char.is_whitespace() {
-                processed.push(' ');
+        (Some(path), _) => {
+            if let Some(size) = fs::metadata(path).map(|m| m.len()).ok().filter(|&size| too_large(size)) {
+                fail(Failure::TooLarge, Some(&source), format!("Error: '{}' is {} bytes, more than --max-size {}", source, size, max_size.unwrap()));
+            }
+            load_document(path, password)
+        }
+        (None, Some(encoded)) => {
+            if encoded.len() > LARGE_BASE64_INPUT {
+                eprintln!(
+                    "Warning: --input-base64 is {} bytes long; command lines are limited to about 32 KB on Windows and a few MB elsewhere, so pass large documents with --input instead",
+                    encoded.len()
+                );
+            }
+            let bytes = decode_base64(encoded).unwrap_or_else(|e| {
+                fail(Failure::InvalidInput, Some(&source), format!("Error decoding --input-base64: {}", e));
+            });
+            if too_large(bytes.len() as u64) {
+                fail(Failure::TooLarge, Some(&source), format!("Error: --input-base64 decodes to {} bytes, more than --max-size {}", bytes.len(), max_size.unwrap()));
+            }
+            if provenance {
+                input_provenance = Some(Provenance::of_bytes(&bytes));
+            }
+            let loaded = Document::load_mem(&bytes).and_then(|doc| unlock_document(doc, password));
+            input_bytes = Some(bytes);
+            loaded
+        }
+        (None, None) => unreachable!("checked above"),
+    };
+    let loaded = if matches.get_flag("repair") {
+        let bytes = || input_bytes.map_or_else(|| fs::read(input_path.expect("file input when no bytes were read")), Ok);
+        repair_document(loaded, bytes, password, &mut warnings, verbose)
+    } else {
+        loaded
+    };
+    let doc = match loaded {
+        Ok((doc, unlocked)) => {
+            if verbose {
+                match unlocked {
+                    Unlocked::NotEncrypted => {}
+                    Unlocked::WithoutPassword => println!("Document is encrypted but readable without a password"),
+                    Unlocked::UserPassword => println!("Document unlocked with the user password"),
+                    Unlocked::OwnerPassword => println!("Document unlocked with the owner password"),
+                }
+                if is_linearized(&doc) {
+                    println!("Document is linearized (web-optimized)");
+                }
+            }
+            doc
+        }
+        Err(lopdf::Error::Decryption(DecryptionError::IncorrectPassword)) => match password {
+            None => fail(
+                Failure::Encrypted,
+                Some(&source),
+                format!("Error: '{}' is encrypted and needs a password; supply it with --password", source),
+            ),
+            Some(_) => fail(
+                Failure::WrongPassword,
+                Some(&source),
+                format!("Error: the password matches neither the owner nor the user password of '{}'", source),
+            ),
+        },
+        Err(e) => {
+            fail(Failure::Extraction, Some(&source), format!("Error extracting text from PDF: {}", e));
+        }
+    };
+
+    if let Some(path) = input_path.filter(|_| provenance && input_provenance.is_none()) {
+        match Provenance::of_file(path) {
+            Ok(provenance) => input_provenance = Some(provenance),
+            Err(e) => {
+                fail(Failure::InputUnreadable, Some(&source), format!("Error reading '{}' for --provenance: {}", source, e));
             }
         }
-        
-        processed.push_str(trimmed);
-        prev_char = trimmed.chars().last().unwrap_or(' ');
     }
-    
-    // Clean up multiple consecutive spaces and normalize whitespace
-    let cleaned = processed
-        .split_whitespace()
-        .collect::<Vec<&str>>()
-        .join(" ");
-    
-    // Add metadata header for AI context
-    let mut result = String::new();
-    result.push_str("=== PDF TEXT EXTRACTION ===\n");
-    result.push_str("This text was extracted from a PDF file for AI processing.\n");
-    result.push_str("Some formatting and layout information may be lost.\n");
-    result.push_str("=== CONTENT BEGINS ===\n\n");
-    result.push_str(&cleaned);
-    result.push_str("\n\n=== CONTENT ENDS ===\n");
-    
-    result
+
+    Input {
+        doc,
+        source,
+        path: input_path.cloned(),
+        warnings,
+        provenance: input_provenance,
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Drops the pages `--skip-cover-pages`, `--skip-last` and `--max-pages` leave
+/// out. Returns the kept and original page counts when `--max-pages` cut the
+/// document short; exits when the options leave no pages or `--max-pages`
+/// refuses the document.
+fn limit_pages(doc: &mut Document, matches: &ArgMatches, source: &str, verbose: bool) -> Option<(u32, usize)> {
+    let skip_first = matches.get_one::<u32>("skip-cover-pages").copied().unwrap_or(0);
+    let skip_last = matches.get_one::<u32>("skip-last").copied().unwrap_or(0);
+    if skip_first > 0 || skip_last > 0 {
+        let page_count = count_pages(doc) as u32;
+        if skip_first.saturating_add(skip_last) >= page_count {
+            fail(
+                Failure::InvalidOptions,
+                Some(source),
+                format!(
+                    "Error: skipping {} cover and {} trailing pages leaves nothing of the {} pages in '{}'",
+                    skip_first, skip_last, page_count, source
+                ),
+            );
+        }
+        keep_pages(doc, skip_first + 1..=page_count - skip_last);
+        if verbose {
+            println!("Skipped {} cover and {} trailing pages", skip_first, skip_last);
+        }
+    }
 
-    #[test]
-    fn test_text_processing() {
-        let raw_text = "This is a test\n   \n\nwith multiple    spaces\nand line breaks";
-        let processed = process_extracted_text(raw_text);
-        
-        assert!(processed.contains("This is a test with multiple spaces and line breaks"));
-        assert!(processed.contains("=== PDF TEXT EXTRACTION ==="));
+    if let Some(&max_pages) = matches.get_one::<u32>("max-pages") {
+        let page_count = count_pages(doc);
+        if page_count > max_pages as usize {
+            if matches.get_one::<String>("max-pages-mode").is_some_and(|mode| mode == "refuse") {
+                fail(Failure::TooLarge, Some(source), format!("Error: '{}' has {} pages, more than --max-pages {}", source, page_count, max_pages));
+            }
+            keep_pages(doc, 1..=max_pages);
+            if verbose {
+                println!("Processing only the first {} of {} pages", max_pages, page_count);
+            }
+            return Some((max_pages, page_count));
+        }
+    }
+    None
+}
+
+/// Prints the `stats` report asked for (`--benchmark`, `--compare-backends`),
+/// if any. Returns whether one was.
+fn print_stats_report(doc: &Document, source: &str, matches: &ArgMatches, options: &ProcessingOptions) -> bool {
+    let json = json_format(matches);
+    if let Some(&runs) = matches.get_one::<u32>("benchmark") {
+        print_benchmark(doc, source, runs as usize, options, json);
+    } else if matches.get_flag("compare-backends") {
+        print_backend_comparison(doc, source, json);
+    } else {
+        return false;
+    }
+    true
+}
+
+/// Prints the `info` report asked for (`--xmp`, `--report-fonts`,
+/// `--scanned-pages`, `--scan-js`), if any. Returns whether one was.
+fn print_info_report(doc: &Document, source: &str, matches: &ArgMatches, warnings: &mut Warnings) -> bool {
+    let json = json_format(matches);
+    if matches.get_flag("xmp") {
+        print_xmp(doc, source, json, warnings);
+    } else if matches.get_flag("report-fonts") {
+        print_fonts(doc, source, json);
+    } else if matches.get_flag("scanned-pages") {
+        print_page_kinds(doc, source, json, warnings);
+    } else if matches.get_flag("scan-js") {
+        let scripts = scan_javascript(doc);
+        if scripts.is_empty() {
+            println!("No JavaScript found in '{}'", source);
+        } else {
+            println!("⚠️ Found {} JavaScript actions in '{}':", scripts.len(), source);
+            for location in &scripts {
+                println!("  {}", location);
+            }
+        }
+    } else {
+        return false;
+    }
+    true
+}
+
+/// Set from `--error-format json` once the command line is parsed.
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Why a run failed. The codes reported by `--error-format json` are part of
+/// the interface, so scripts can branch on them; keep them stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// The command line could not be parsed, or named no input.
+    Usage,
+    /// The options do not work together or with this input.
+    InvalidOptions,
+    InputNotFound,
+    InputUnreadable,
+    /// The input is not what was expected, like malformed base64.
+    InvalidInput,
+    /// Refused by `--max-size` or `--max-pages`.
+    TooLarge,
+    Encrypted,
+    WrongPassword,
+    NotPortfolio,
+    Template,
+    Extraction,
+    Output,
+    /// The user quit at an overwrite prompt.
+    Aborted,
+}
+
+impl Failure {
+    fn code(self) -> &'static str {
+        match self {
+            Failure::Usage => "Usage",
+            Failure::InvalidOptions => "InvalidOptions",
+            Failure::InputNotFound => "InputNotFound",
+            Failure::InputUnreadable => "InputUnreadable",
+            Failure::InvalidInput => "InvalidInput",
+            Failure::TooLarge => "TooLarge",
+            Failure::Encrypted => "Encrypted",
+            Failure::WrongPassword => "WrongPassword",
+            Failure::NotPortfolio => "NotPortfolio",
+            Failure::Template => "Template",
+            Failure::Extraction => "Extraction",
+            Failure::Output => "Output",
+            Failure::Aborted => "Aborted",
+        }
+    }
+
+    /// Usage errors exit with 2 like clap's own; everything else with 1.
+    fn exit_code(self) -> i32 {
+        match self {
+            Failure::Usage => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Reports a fatal error and exits. The message goes to stderr as it is, or
+/// with `--error-format json` as a JSON object with the failure's code and the
+/// path of the file concerned.
+fn fail(failure: Failure, path: Option<&str>, message: impl Display) -> ! {
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        eprintln!("{}", error_json(failure, path, &message.to_string()));
+    } else {
+        eprintln!("{}", message);
+    }
+    process::exit(failure.exit_code());
+}
+
+/// The `--error-format json` object, with the "Error: " lead-in of the text
+/// message dropped since the object already says it is one.
+fn error_json(failure: Failure, path: Option<&str>, message: &str) -> serde_json::Value {
+    let message = ["Error: ", "error: "]
+        .iter()
+        .find_map(|lead| message.strip_prefix(lead))
+        .unwrap_or(message);
+    json!({ "error": failure.code(), "message": message, "path": path })
+}
+
+/// Whether the raw command line asks for JSON errors, for reporting errors in
+/// the command line itself.
+fn json_errors_requested(args: &[OsString]) -> bool {
+    args.windows(2).any(|pair| pair[0] == "--error-format" && pair[1] == "json")
+        || args.iter().any(|arg| arg == "--error-format=json")
+}
+
+/// Which password, if any, was needed to open a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Unlocked {
+    NotEncrypted,
+    /// Encrypted with an empty user password, typically to restrict permissions
+    /// with an owner password only.
+    WithoutPassword,
+    UserPassword,
+    OwnerPassword,
+}
+
+/// Loads a PDF with lopdf, decrypting it when needed. lopdf already decrypts
+/// documents whose user password is empty while loading, so a password is only
+/// required when the content is actually unreadable. A given password is tried
+/// as the user password first and then as the owner password.
+fn load_document(file_path: &Path, password: Option<&str>) -> Result<(Document, Unlocked), lopdf::Error> {
+    unlock_document(read_document(file_path)?, password)
+}
+
+/// The decryption half of `load_document`, for documents not read from a file.
+fn unlock_document(mut doc: Document, password: Option<&str>) -> Result<(Document, Unlocked), lopdf::Error> {
+    if !doc.is_encrypted() {
+        let unlocked = if doc.encryption_state.is_some() {
+            Unlocked::WithoutPassword
+        } else {
+            Unlocked::NotEncrypted
+        };
+        return Ok((doc, unlocked));
+    }
+
+    let password = password.ok_or(DecryptionError::IncorrectPassword)?;
+    if doc.authenticate_user_password(password).is_ok() {
+        doc.decrypt(password)?;
+        Ok((doc, Unlocked::UserPassword))
+    } else if doc.authenticate_owner_password(password).is_ok() {
+        match user_password_from_owner(&doc, password) {
+            Some(user_password) => doc.decrypt_raw(user_password)?,
+            // Revision 5+ derives the file key from the owner password directly
+            None => doc.decrypt(password)?,
+        }
+        Ok((doc, Unlocked::OwnerPassword))
+    } else {
+        Err(DecryptionError::IncorrectPassword.into())
+    }
+}
+
+/// Drops every page outside `keep` from the document. lopdf deletes the page
+/// objects and fixes up the page tree counts, so later page lookups and
+/// extraction only see the pages that are left, numbered from 1 again.
+fn keep_pages(doc: &mut Document, keep: RangeInclusive<u32>) {
+    let extra: Vec<u32> = doc.get_pages().into_keys().filter(|page| !keep.contains(page)).collect();
+    doc.delete_pages(&extra);
+}
+
+/// Base64 inputs longer than this get a warning about command-line limits.
+const LARGE_BASE64_INPUT: usize = 32 * 1024;
+
+/// Decodes a standard base64 string, ignoring the line breaks and other
+/// whitespace that encoders and shell quoting tend to leave in.
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    use base64::Engine;
+
+    let compact: String = encoded.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    base64::engine::general_purpose::STANDARD.decode(compact)
+}
+
+/// Whether the input is a pipe, socket or device rather than a regular file.
+/// Those can only be read once from start to end, so lopdf cannot parse them
+/// in place.
+fn is_stream(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| !metadata.is_file() && !metadata.is_dir())
+}
+
+/// Reads a stream input into memory to parse it from there, failing once it
+/// grows past `limit` bytes.
+fn read_stream(path: &Path, limit: Option<u64>) -> std::io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    let mut bytes = Vec::new();
+    match limit {
+        Some(limit) => {
+            file.take(limit + 1).read_to_end(&mut bytes)?;
+            if bytes.len() as u64 > limit {
+                return Err(std::io::Error::other(format!(
+                    "the stream is larger than --max-size {} bytes",
+                    limit
+                )));
+            }
+        }
+        None => {
+            file.read_to_end(&mut bytes)?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Parses the input file through a memory map, so large files are not copied
+/// into memory first. Falls back to a normal read when the file cannot be
+/// mapped, as with pipes and other special files.
+#[cfg(feature = "mmap")]
+fn read_document(file_path: &Path) -> Result<Document, lopdf::Error> {
+    let file = fs::File::open(file_path)?;
+    // Safety: the map is only read while parsing, and PDFBot does not modify
+    // its input. Another process truncating the file meanwhile is not guarded
+    // against, as with any mmap reader.
+    match unsafe { memmap2::Mmap::map(&file) } {
+        Ok(map) => Document::load_mem(&map),
+        Err(_) => Document::load_from(file),
+    }
+}
+
+#[cfg(not(feature = "mmap"))]
+fn read_document(file_path: &Path) -> Result<Document, lopdf::Error> {
+    Document::load(file_path)
+}
+
+/// Whether the file is linearized ("fast web view"): the first object in the
+/// file is then a linearization parameter dictionary. lopdf always parses the
+/// whole file, so this is only reported; reading just the first page does not
+/// get any faster.
+fn is_linearized(doc: &Document) -> bool {
+    let first = doc
+        .reference_table
+        .entries
+        .iter()
+        .filter_map(|(&id, entry)| match *entry {
+            lopdf::xref::XrefEntry::Normal { offset, generation } => Some((offset, (id, generation))),
+            _ => None,
+        })
+        .min();
+    first
+        .and_then(|(_, id)| doc.objects.get(&id))
+        .and_then(|object| object.as_dict().ok())
+        .is_some_and(|dict| dict.has(b"Linearized"))
+}
+
+/// Padding string from the PDF specification's standard security handler.
+const PASSWORD_PADDING: [u8; 32] = [
+    0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08,
+    0x2E, 0x2E, 0x00, 0xB6, 0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+];
+
+/// Recovers the padded user password from the owner password for revision 2-4
+/// encryption (Algorithm 7 of the PDF specification). lopdf can authenticate
+/// an owner password but only derives the file key from a user password, so
+/// this lets owner-password-only access decrypt the document. Returns `None`
+/// for other revisions.
+fn user_password_from_owner(doc: &Document, owner_password: &str) -> Option<Vec<u8>> {
+    let encrypt = doc.get_encrypted().ok()?;
+    let revision = encrypt.get(b"R").and_then(Object::as_i64).ok()?;
+    if !(2..=4).contains(&revision) {
+        return None;
+    }
+    let key_len = if revision == 2 {
+        5
+    } else {
+        encrypt.get(b"Length").and_then(Object::as_i64).map_or(5, |bits| (bits / 8) as usize)
+    };
+    let owner_value = encrypt.get(b"O").and_then(Object::as_str).ok()?;
+
+    let password = owner_password.as_bytes();
+    let len = password.len().min(32);
+    let mut hash = Md5::new()
+        .chain_update(&password[..len])
+        .chain_update(&PASSWORD_PADDING[..32 - len])
+        .finalize()
+        .to_vec();
+    if revision >= 3 {
+        for _ in 0..50 {
+            hash = Md5::digest(&hash[..key_len]).to_vec();
+        }
+    }
+    let key = &hash[..key_len];
+
+    let mut user_password = owner_value.get(..32)?.to_vec();
+    if revision == 2 {
+        user_password = rc4(key, &user_password);
+    } else {
+        for i in (0..=19u8).rev() {
+            let round_key: Vec<u8> = key.iter().map(|b| b ^ i).collect();
+            user_password = rc4(&round_key, &user_password);
+        }
+    }
+    Some(user_password)
+}
+
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: Vec<u8> = (0..=255).collect();
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            byte ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+        })
+        .collect()
+}
+
+fn extract_document_text(doc: &Document) -> Result<String, OutputError> {
+    let mut text = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut text);
+        output_doc(doc, &mut output)?;
+    }
+    Ok(text)
+}
+
+/// A raw extraction kept in `--cache-dir`. Entries are named by the input's
+/// SHA-256, plus the pages dropped from it when any were, and start with a line
+/// naming the PDFBot version, the input hash and the hash of the cached text.
+/// An entry whose line does not match is stale or damaged and is replaced.
+struct ExtractionCache {
+    path: PathBuf,
+    input_sha256: String,
+}
+
+impl ExtractionCache {
+    /// `pages` is the `--skip-cover-pages`, `--skip-last` and truncating
+    /// `--max-pages` counts, zero when unused.
+    fn new(dir: &Path, input_sha256: String, pages: (u32, u32, u32)) -> ExtractionCache {
+        let name = match pages {
+            (0, 0, 0) => format!("{}.txt", input_sha256),
+            (first, last, kept) => format!("{}-skip{}-{}-max{}.txt", input_sha256, first, last, kept),
+        };
+        ExtractionCache {
+            path: dir.join(name),
+            input_sha256,
+        }
+    }
+
+    fn header(&self, text: &str) -> String {
+        format!(
+            "pdfbot-cache {} {} {}",
+            env!("CARGO_PKG_VERSION"),
+            self.input_sha256,
+            hex(&Sha256::digest(text.as_bytes()))
+        )
+    }
+
+    /// The cached text, if there is a valid entry.
+    fn load(&self) -> Option<String> {
+        let entry = fs::read_to_string(&self.path).ok()?;
+        let (header, text) = entry.split_once('\n')?;
+        (header == self.header(text)).then(|| text.to_string())
+    }
+
+    fn store(&self, text: &str, options: &ProcessingOptions) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            create_output_dir(dir, options)?;
+        }
+        write_file(&self.path, format!("{}\n{}", self.header(text), text).as_bytes(), options)
+    }
+}
+
+/// Reuses the cached extraction when there is one, or extracts the document
+/// and caches the result. Failing to write the cache is only a warning.
+fn extract_with_cache(
+    doc: &Document,
+    verbose: bool,
+    cache: &ExtractionCache,
+    options: &ProcessingOptions,
+    warnings: &mut Warnings,
+) -> Result<String, OutputError> {
+    if let Some(text) = cache.load() {
+        if verbose {
+            println!("Using cached extraction {}", cache.path.display());
+        }
+        return Ok(text);
+    }
+    let text = extract_document_text(doc)?;
+    match cache.store(&text, options) {
+        Ok(()) if verbose => println!("Cached extraction in {}", cache.path.display()),
+        Ok(()) => {}
+        Err(e) => warnings.warn(None, "cache_failed", format!("could not write '{}': {}", cache.path.display(), e)),
+    }
+    Ok(text)
+}
+
+/// Extracts one page for `--page`, reporting pages past the end as an error
+/// rather than an empty result. Only that page is decoded.
+fn extract_single_page(doc: &Document, page: u32) -> Result<String, Box<dyn std::error::Error>> {
+    let page_count = count_pages(doc);
+    if page as usize > page_count {
+        return Err(format!("page {} does not exist (the document has {} pages)", page, page_count).into());
+    }
+    Ok(extract_pages_text(doc, [page])?)
+}
+
+/// Extracts the text of the given 1-based pages of an already loaded document.
+fn extract_pages_text(doc: &Document, pages: impl IntoIterator<Item = u32>) -> Result<String, OutputError> {
+    let mut text = String::new();
+    {
+        let mut output = PlainTextOutput::new(&mut text);
+        for page in pages {
+            output_doc_page(doc, &mut output, page)?;
+        }
+    }
+    Ok(text)
+}
+
+/// A glyph reported by pdf-extract, positioned in PDF user space (origin at the
+/// bottom-left of the page, `y` on the text baseline).
+#[derive(Debug, Clone)]
+struct PositionedChar {
+    page: u32,
+    /// Index of the text-showing operation that produced the glyph.
+    run: usize,
+    x: f64,
+    y: f64,
+    width: f64,
+    font_size: f64,
+    text: String,
+}
+
+impl PositionedChar {
+    /// A point near the middle of the glyph, used for hit-testing it against
+    /// page regions.
+    fn center(&self) -> (f64, f64) {
+        (self.x + self.width / 2.0, self.y + self.font_size * 0.3)
+    }
+}
+
+/// An `OutputDev` that records every glyph with its position instead of
+/// rendering text.
+#[derive(Default)]
+struct PositionCollector {
+    page: u32,
+    run: usize,
+    chars: Vec<PositionedChar>,
+}
+
+impl OutputDev for PositionCollector {
+    fn begin_page(&mut self, page_num: u32, _media_box: &MediaBox, _art_box: Option<(f64, f64, f64, f64)>) -> Result<(), OutputError> {
+        self.page = page_num;
+        Ok(())
+    }
+
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn output_character(&mut self, trm: &Transform, width: f64, _spacing: f64, font_size: f64, char: &str) -> Result<(), OutputError> {
+        // The text rendering matrix does not include the font size, so scale it
+        // by the matrix's own scale factor to get the size on the page
+        let scale = (trm.m11 * trm.m22 - trm.m12 * trm.m21).abs().sqrt();
+        let size = font_size * scale;
+        self.chars.push(PositionedChar {
+            page: self.page,
+            run: self.run,
+            x: trm.m31,
+            y: trm.m32,
+            width: width * size,
+            font_size: size,
+            text: char.to_string(),
+        });
+        Ok(())
+    }
+
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        // pdf-extract begins a "word" for every text-showing operator
+        self.run += 1;
+        Ok(())
+    }
+
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+/// Collects the positioned glyphs of the given 1-based pages.
+fn collect_positions(doc: &Document, pages: impl IntoIterator<Item = u32>) -> Result<Vec<PositionedChar>, OutputError> {
+    let mut collector = PositionCollector::default();
+    for page in pages {
+        output_doc_page(doc, &mut collector, page)?;
+    }
+    Ok(collector.chars)
+}
+
+/// Writes one line per text run with its page, starting position and font
+/// size, for diagnosing reading-order problems.
+fn trace_positions(doc: &Document, out: &mut dyn std::io::Write) -> Result<(), Box<dyn std::error::Error>> {
+    let chars = collect_positions(doc, doc.get_pages().into_keys())?;
+
+    for run in chars.chunk_by(|a, b| a.run == b.run) {
+        let first = &run[0];
+        writeln!(
+            out,
+            "page {} x={:.2} y={:.2} size={:.2} {:?}",
+            first.page,
+            first.x,
+            first.y,
+            first.font_size,
+            run.iter().map(|c| c.text.as_str()).collect::<String>()
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Describes every text run on one page as JSON: its text, base font, size
+/// and bounding box in PDF user space. The box spans from the baseline to one
+/// font size above it, since glyph ascent and descent are not available.
+fn layout_json(doc: &Document, page: u32) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let page_id = *doc
+        .get_pages()
+        .get(&page)
+        .ok_or_else(|| format!("page {} does not exist", page))?;
+    let run_info = page_runs(doc, page_id);
+    let chars = collect_positions(doc, [page])?;
+
+    let runs: Vec<serde_json::Value> = chars
+        .chunk_by(|a, b| a.run == b.run)
+        .map(|run| {
+            let left = run.iter().map(|c| c.x).fold(f64::INFINITY, f64::min);
+            let right = run.iter().map(|c| c.x + c.width).fold(f64::NEG_INFINITY, f64::max);
+            let bottom = run.iter().map(|c| c.y).fold(f64::INFINITY, f64::min);
+            let top = run.iter().map(|c| c.y + c.font_size).fold(f64::NEG_INFINITY, f64::max);
+            json!({
+                "text": lf_line_endings(&run.iter().map(|c| c.text.as_str()).collect::<String>()),
+                "font": run_info.get(run[0].run - 1).and_then(|info| info.font.clone()),
+                "size": run[0].font_size,
+                "bbox": [left, bottom, right, top],
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "page": page,
+        "media_box": inherited_page_attribute(doc, page_id, b"MediaBox")
+            .and_then(|b| b.as_array().ok())
+            .map(|b| b.iter().filter_map(|n| n.as_float().ok()).collect::<Vec<_>>()),
+        "runs": runs,
+    }))
+}
+
+/// Text fields in JSON output always use LF, whatever `--newline` says; that
+/// option only applies to text files written by `write_output`.
+fn lf_line_endings(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Lists every text line of the document as CSV with a header row. Positions
+/// are in PDF user space like `--layout-json`: points from the bottom-left
+/// corner of the page, with `y` on the line's baseline and `height` the
+/// largest font size on the line.
+fn layout_csv(doc: &Document) -> Result<String, Box<dyn std::error::Error>> {
+    let mut csv = String::from("page,x,y,width,height,text\n");
+    for page in doc.get_pages().into_keys() {
+        let chars = collect_positions(doc, [page])?;
+        for line in chars.chunk_by(|a, b| (a.y - b.y).abs() <= a.font_size * 0.5) {
+            let text = chars_to_text(line);
+            if text.trim().is_empty() {
+                continue;
+            }
+            let left = line.iter().map(|c| c.x).fold(f64::INFINITY, f64::min);
+            let right = line.iter().map(|c| c.x + c.width).fold(f64::NEG_INFINITY, f64::max);
+            let baseline = line.iter().map(|c| c.y).fold(f64::INFINITY, f64::min);
+            let height = line.iter().map(|c| c.font_size).fold(0.0, f64::max);
+            csv.push_str(&format!(
+                "{},{:.2},{:.2},{:.2},{:.2},{}\n",
+                page,
+                left,
+                baseline,
+                right - left,
+                height,
+                csv_field(text.trim())
+            ));
+        }
+    }
+    Ok(csv)
+}
+
+/// Quotes a CSV field when it contains a comma, quote or line break, doubling
+/// any quotes inside.
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        std::borrow::Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        std::borrow::Cow::Borrowed(field)
+    }
+}
+
+/// What the content stream says about one text run that pdf-extract does not
+/// pass on to an `OutputDev`.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct RunInfo {
+    /// Base font name of the font selected with `Tf`.
+    font: Option<String>,
+    /// Marked-content ID of the innermost enclosing `BDC` that has one.
+    mcid: Option<i64>,
+}
+
+/// Describes each text run on a page, in the order pdf-extract reports runs:
+/// one per string shown by `Tj` or inside a `TJ` array, descending into
+/// XObjects where they are drawn.
+fn page_runs(doc: &Document, page_id: lopdf::ObjectId) -> Vec<RunInfo> {
+    let mut runs = Vec::new();
+    if let Ok(content) = doc.get_page_content(page_id) {
+        let resources = inherited_page_attribute(doc, page_id, b"Resources").and_then(|r| r.as_dict().ok());
+        content_runs(doc, &content, resources, None, &mut runs, 0);
+    }
+    runs
+}
+
+fn content_runs(
+    doc: &Document,
+    content: &[u8],
+    resources: Option<&Dictionary>,
+    outer_mcid: Option<i64>,
+    runs: &mut Vec<RunInfo>,
+    depth: usize,
+) {
+    let Ok(content) = Content::decode(content) else {
+        return;
+    };
+    let resource = |kind: &[u8], name: &Object| {
+        let name = name.as_name().ok()?;
+        resources?.get_deref(kind, doc).and_then(Object::as_dict).ok()?.get_deref(name, doc).ok()
+    };
+
+    let mut font: Option<String> = None;
+    let mut saved = Vec::new();
+    let mut marked: Vec<Option<i64>> = Vec::new();
+    let current = |font: &Option<String>, marked: &[Option<i64>]| RunInfo {
+        font: font.clone(),
+        mcid: marked.iter().rev().find_map(|mcid| *mcid).or(outer_mcid),
+    };
+
+    for operation in &content.operations {
+        let operand = operation.operands.first().unwrap_or(&Object::Null);
+        match operation.operator.as_str() {
+            "q" => saved.push(font.clone()),
+            "Q" => font = saved.pop().unwrap_or_default(),
+            "Tf" => {
+                font = resource(b"Font", operand)
+                    .and_then(|f| f.as_dict().ok())
+                    .and_then(|f| f.get(b"BaseFont").and_then(Object::as_name).ok())
+                    .map(|name| String::from_utf8_lossy(name).into_owned());
+            }
+            "BMC" => marked.push(None),
+            "BDC" => {
+                // The property list is inline or named in /Properties
+                let properties = match operation.operands.get(1) {
+                    Some(Object::Dictionary(properties)) => Some(properties),
+                    Some(name) => resource(b"Properties", name).and_then(|p| p.as_dict().ok()),
+                    None => None,
+                };
+                marked.push(properties.and_then(|p| p.get(b"MCID").and_then(Object::as_i64).ok()));
+            }
+            "EMC" => {
+                marked.pop();
+            }
+            "Tj" => runs.push(current(&font, &marked)),
+            "TJ" => {
+                let strings = operand.as_array().map_or(0, |items| items.iter().filter(|i| i.as_str().is_ok()).count());
+                runs.extend(std::iter::repeat_n(current(&font, &marked), strings));
+            }
+            "Do" if depth < 8 => {
+                if let Some(Object::Stream(xobject)) = resource(b"XObject", operand) {
+                    let inner = xobject.dict.get_deref(b"Resources", doc).and_then(Object::as_dict).ok().or(resources);
+                    if let Ok(data) = xobject.get_plain_content() {
+                        let mcid = current(&font, &marked).mcid;
+                        content_runs(doc, &data, inner, mcid, runs, depth + 1);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Structure types that continue the surrounding block instead of starting a
+/// new one.
+const INLINE_STRUCTURE_TYPES: &[&[u8]] = &[
+    b"Span", b"Link", b"Quote", b"Reference", b"Code", b"Em", b"Strong", b"Sub", b"Sup", b"Annot", b"Ruby",
+    b"Warichu", b"BibEntry", b"Lbl", b"LBody",
+];
+
+/// Extracts text by walking the structure tree of a tagged PDF, which records
+/// the author's logical reading order. Each block-level element becomes its
+/// own line, while inline elements such as spans and links stay within their
+/// block. Content outside the tree, such as running headers marked as
+/// artifacts, is left out. Returns `None` when the document is not tagged.
+fn extract_struct_tree(doc: &Document) -> Result<Option<String>, OutputError> {
+    let Ok(root) = doc
+        .catalog()
+        .and_then(|catalog| catalog.get_deref(b"StructTreeRoot", doc))
+        .and_then(Object::as_dict)
+    else {
+        return Ok(None);
+    };
+
+    let pages = doc.get_pages();
+    // Glyphs of each marked-content sequence, keyed by page and MCID
+    let mut content: HashMap<(u32, i64), Vec<PositionedChar>> = HashMap::new();
+    for (&page, &page_id) in &pages {
+        let runs = page_runs(doc, page_id);
+        for c in collect_positions(doc, [page])? {
+            if let Some(mcid) = runs.get(c.run - 1).and_then(|info| info.mcid) {
+                content.entry((page, mcid)).or_default().push(c);
+            }
+        }
+    }
+
+    let mut walker = StructTreeWalker {
+        doc,
+        role_map: root.get_deref(b"RoleMap", doc).and_then(Object::as_dict).ok(),
+        page_numbers: pages.iter().map(|(&page, &id)| (id, page)).collect(),
+        content,
+        pending: Vec::new(),
+        block: String::new(),
+        blocks: Vec::new(),
+    };
+    if let Ok(kids) = root.get(b"K") {
+        walker.visit(kids, None, 0);
+    }
+    walker.end_block();
+
+    Ok(Some(walker.blocks.join("\n")))
+}
+
+/// State for `extract_struct_tree`: the marked content still unclaimed and
+/// the text assembled so far.
+struct StructTreeWalker<'a> {
+    doc: &'a Document,
+    role_map: Option<&'a Dictionary>,
+    page_numbers: HashMap<lopdf::ObjectId, u32>,
+    content: HashMap<(u32, i64), Vec<PositionedChar>>,
+    /// Glyphs of the current block not yet turned into text.
+    pending: Vec<PositionedChar>,
+    block: String,
+    blocks: Vec<String>,
+}
+
+impl StructTreeWalker<'_> {
+    /// Visits a /K entry: a structure element, a marked-content reference (an
+    /// MCID or MCR dictionary), an object reference, or an array of these.
+    fn visit(&mut self, kids: &Object, page: Option<u32>, depth: usize) {
+        let Ok((_, kids)) = self.doc.dereference(kids) else {
+            return;
+        };
+        match kids {
+            Object::Array(kids) => {
+                for kid in kids {
+                    self.visit(kid, page, depth);
+                }
+            }
+            Object::Integer(mcid) => self.claim(page, *mcid),
+            Object::Dictionary(kid) => match kid.get(b"Type").and_then(Object::as_name) {
+                Ok(b"MCR") => {
+                    let page = self.page_of(kid).or(page);
+                    if let Ok(mcid) = kid.get(b"MCID").and_then(Object::as_i64) {
+                        self.claim(page, mcid);
+                    }
+                }
+                // Object references point at annotations and XObjects, not text
+                Ok(b"OBJR") => {}
+                _ if depth < 64 => self.visit_element(kid, page, depth + 1),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn visit_element(&mut self, element: &Dictionary, page: Option<u32>, depth: usize) {
+        let page = self.page_of(element).or(page);
+        let inline = INLINE_STRUCTURE_TYPES.contains(&self.standard_type(element));
+        if !inline {
+            self.end_block();
+        }
+
+        match element.get_deref(b"ActualText", self.doc).and_then(Object::as_str) {
+            Ok(actual_text) => {
+                self.settle();
+                self.append(&decode_pdf_string(actual_text));
+            }
+            Err(_) => {
+                if let Ok(kids) = element.get(b"K") {
+                    self.visit(kids, page, depth);
+                }
+            }
+        }
+
+        if !inline {
+            self.end_block();
+        }
+    }
+
+    /// The element's structure type, following the /RoleMap for custom types.
+    fn standard_type<'b>(&'b self, element: &'b Dictionary) -> &'b [u8] {
+        let mut kind: &[u8] = element.get(b"S").and_then(Object::as_name).unwrap_or_default();
+        for _ in 0..8 {
+            match self.role_map.and_then(|map| map.get(kind).and_then(Object::as_name).ok()) {
+                Some(mapped) if mapped != kind => kind = mapped,
+                _ => break,
+            }
+        }
+        kind
+    }
+
+    fn page_of(&self, dict: &Dictionary) -> Option<u32> {
+        let page_id = dict.get(b"Pg").and_then(Object::as_reference).ok()?;
+        self.page_numbers.get(&page_id).copied()
+    }
+
+    fn claim(&mut self, page: Option<u32>, mcid: i64) {
+        if let Some(chars) = page.and_then(|page| self.content.remove(&(page, mcid))) {
+            self.pending.extend(chars);
+        }
+    }
+
+    /// Turns the glyphs collected so far into text on the current block.
+    fn settle(&mut self) {
+        if !self.pending.is_empty() {
+            let text = chars_to_text(&self.pending);
+            self.pending.clear();
+            self.append(&text);
+        }
+    }
+
+    fn append(&mut self, text: &str) {
+        let needs_space = !self.block.is_empty()
+            && !self.block.ends_with(char::is_whitespace)
+            && !text.starts_with(char::is_whitespace);
+        if needs_space {
+            self.block.push(' ');
+        }
+        self.block.push_str(text);
+    }
+
+    fn end_block(&mut self) {
+        self.settle();
+        let block = self.block.trim();
+        if !block.is_empty() {
+            self.blocks.push(block.to_string());
+        }
+        self.block.clear();
+    }
+}
+
+/// Reassembles text from glyphs in content-stream order, starting a new line
+/// when the baseline moves and inserting a space where there is a visible gap
+/// between neighbouring glyphs.
+fn chars_to_text<'a>(chars: impl IntoIterator<Item = &'a PositionedChar>) -> String {
+    let mut text = String::new();
+    let mut previous: Option<&PositionedChar> = None;
+
+    for c in chars {
+        if let Some(prev) = previous {
+            if (c.y - prev.y).abs() > prev.font_size * 0.5 {
+                text.push('\n');
+            } else if c.x > prev.x + prev.width + prev.font_size * 0.1 && !text.ends_with(' ') && c.text != " " {
+                text.push(' ');
+            }
+        }
+        text.push_str(&c.text);
+        previous = Some(c);
+    }
+
+    text
+}
+
+/// A reviewer annotation read from a page's /Annots array.
+#[derive(Debug)]
+struct Annotation {
+    page: u32,
+    kind: String,
+    author: Option<String>,
+    contents: Option<String>,
+    /// For highlights, the page text underneath the highlighted region.
+    highlighted: Option<String>,
+    /// Lower edge of the annotation's /Rect, used to place it among the page's
+    /// lines with `--flatten-annotations`.
+    bottom: Option<f64>,
+}
+
+/// Reads Text, Highlight and Popup annotations from every page. Popups that
+/// belong to another annotation are skipped since they only display their
+/// parent's contents.
+fn read_annotations(doc: &Document) -> Result<Vec<Annotation>, Box<dyn std::error::Error>> {
+    let mut annotations = Vec::new();
+
+    for (page, page_id) in doc.get_pages() {
+        let mut page_chars: Option<Vec<PositionedChar>> = None;
+
+        for annot in doc.get_page_annotations(page_id)? {
+            let kind = match annot.get(b"Subtype").and_then(Object::as_name) {
+                Ok(b"Text") => "Note",
+                Ok(b"Highlight") => "Highlight",
+                Ok(b"Popup") if !annot.has(b"Parent") => "Popup",
+                _ => continue,
+            };
+            let text_field = |key: &[u8]| {
+                annot
+                    .get_deref(key, doc)
+                    .and_then(Object::as_str)
+                    .map(decode_pdf_string)
+                    .ok()
+                    .filter(|value| !value.trim().is_empty())
+            };
+
+            let highlighted = if kind == "Highlight" {
+                if page_chars.is_none() {
+                    page_chars = Some(collect_positions(doc, [page])?);
+                }
+                highlighted_text(doc, annot, page_chars.as_deref().unwrap_or_default())
+            } else {
+                None
+            };
+
+            let contents = text_field(b"Contents");
+            if contents.is_none() && highlighted.is_none() {
+                continue;
+            }
+            annotations.push(Annotation {
+                page,
+                kind: kind.to_string(),
+                author: text_field(b"T"),
+                contents,
+                highlighted,
+                bottom: number_array(doc, annot, b"Rect")
+                    .filter(|rect| rect.len() == 4)
+                    .map(|rect| rect[1].min(rect[3])),
+            });
+        }
+    }
+
+    Ok(annotations)
+}
+
+/// Reads a numeric array entry such as /Rect or /QuadPoints.
+fn number_array(doc: &Document, dict: &Dictionary, key: &[u8]) -> Option<Vec<f64>> {
+    let array = dict.get_deref(key, doc).and_then(Object::as_array).ok()?;
+    array.iter().map(|n| n.as_float().ok().map(f64::from)).collect()
+}
+
+/// Finds the glyphs covered by a highlight's quadrilaterals (or its /Rect when
+/// it has none) and joins them back into text.
+fn highlighted_text(doc: &Document, annot: &Dictionary, chars: &[PositionedChar]) -> Option<String> {
+    let regions: Vec<(f64, f64, f64, f64)> = match number_array(doc, annot, b"QuadPoints") {
+        Some(points) if points.len() >= 8 => points
+            .chunks_exact(8)
+            .map(|quad| {
+                let xs = [quad[0], quad[2], quad[4], quad[6]];
+                let ys = [quad[1], quad[3], quad[5], quad[7]];
+                (
+                    xs.iter().cloned().fold(f64::INFINITY, f64::min),
+                    ys.iter().cloned().fold(f64::INFINITY, f64::min),
+                    xs.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                    ys.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                )
+            })
+            .collect(),
+        _ => {
+            let rect = number_array(doc, annot, b"Rect").filter(|rect| rect.len() == 4)?;
+            vec![(rect[0].min(rect[2]), rect[1].min(rect[3]), rect[0].max(rect[2]), rect[1].max(rect[3]))]
+        }
+    };
+
+    let covered = chars.iter().filter(|c| {
+        let (x, y) = c.center();
+        regions
+            .iter()
+            .any(|&(left, bottom, right, top)| x >= left && x <= right && y >= bottom && y <= top)
+    });
+    let text = normalize_whitespace(&chars_to_text(covered), TabMode::Collapse);
+
+    (!text.is_empty()).then_some(text)
+}
+
+/// Renders annotations as a "Comments:" section with one line per annotation.
+/// Pages are named by their entry in `labels`, or their number when missing.
+fn format_comments(annotations: &[Annotation], labels: &HashMap<u32, String>) -> String {
+    let mut section = String::from("Comments:");
+
+    for annotation in annotations {
+        let page = labels.get(&annotation.page).cloned().unwrap_or_else(|| annotation.page.to_string());
+        section.push_str(&format!("\n[Page {}] {}", page, describe_annotation(annotation)));
+    }
+
+    section
+}
+
+/// Reads the catalog's /PageLabels number tree into a label for every page,
+/// keyed by page number. Each range gives a numbering style (/S), a /P prefix
+/// and a starting value (/St) for the pages from its key onwards. The tree
+/// still counts the `skipped` pages `--skip-cover-pages` removed, so page 1
+/// is looked up as page `skipped + 1`. Empty when the document has no page
+/// labels.
+fn page_labels(doc: &Document, skipped: u32) -> HashMap<u32, String> {
+    let Ok(tree) = doc.catalog().and_then(|catalog| catalog.get_deref(b"PageLabels", doc)).and_then(Object::as_dict) else {
+        return HashMap::new();
+    };
+    let mut ranges = Vec::new();
+    collect_number_tree(doc, tree, &mut ranges, 0);
+    ranges.sort_by_key(|&(start, _)| start);
+
+    let mut labels = HashMap::new();
+    for page in doc.get_pages().into_keys() {
+        let index = i64::from(page) + i64::from(skipped) - 1;
+        let Some((start, range)) = ranges.iter().rev().find(|(start, _)| *start <= index) else {
+            continue;
+        };
+        let prefix = range
+            .get_deref(b"P", doc)
+            .and_then(Object::as_str)
+            .map(decode_pdf_string)
+            .unwrap_or_default();
+        let first = range.get(b"St").and_then(Object::as_i64).unwrap_or(1);
+        let number = first + index - start;
+        let numeral = match range.get(b"S").and_then(Object::as_name) {
+            Ok(b"D") => number.to_string(),
+            Ok(b"R") => roman_numeral(number),
+            Ok(b"r") => roman_numeral(number).to_lowercase(),
+            Ok(b"A") => letter_numeral(number),
+            Ok(b"a") => letter_numeral(number).to_lowercase(),
+            // A range without a style labels its pages with the prefix alone
+            _ => String::new(),
+        };
+        labels.insert(page, prefix + &numeral);
+    }
+    labels
+}
+
+/// Gathers the (key, dictionary) pairs of a number tree, descending into /Kids.
+fn collect_number_tree<'a>(doc: &'a Document, node: &'a Dictionary, entries: &mut Vec<(i64, &'a Dictionary)>, depth: usize) {
+    if let Ok(nums) = node.get(b"Nums").and_then(Object::as_array) {
+        for pair in nums.chunks_exact(2) {
+            if let (Ok(key), Ok(value)) = (pair[0].as_i64(), doc.dereference(&pair[1]).and_then(|(_, v)| v.as_dict())) {
+                entries.push((key, value));
+            }
+        }
+    }
+    if depth < 32 {
+        for kid in node.get(b"Kids").and_then(Object::as_array).into_iter().flatten() {
+            if let Ok(kid) = doc.dereference(kid).and_then(|(_, kid)| kid.as_dict()) {
+                collect_number_tree(doc, kid, entries, depth + 1);
+            }
+        }
+    }
+}
+
+/// Upper-case Roman numeral, as page labels with style /R use.
+fn roman_numeral(mut number: i64) -> String {
+    const NUMERALS: [(i64, &str); 13] = [
+        (1000, "M"), (900, "CM"), (500, "D"), (400, "CD"), (100, "C"), (90, "XC"),
+        (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I"),
+    ];
+    if number <= 0 {
+        return number.to_string();
+    }
+    let mut numeral = String::new();
+    for (value, symbol) in NUMERALS {
+        while number >= value {
+            numeral.push_str(symbol);
+            number -= value;
+        }
+    }
+    numeral
+}
+
+/// Upper-case letter numbering for style /A: A to Z, then AA to ZZ, AAA, ...
+fn letter_numeral(number: i64) -> String {
+    if number <= 0 {
+        return number.to_string();
+    }
+    let letter = (b'A' + ((number - 1) % 26) as u8) as char;
+    letter.to_string().repeat(((number - 1) / 26 + 1) as usize)
+}
+
+/// Describes one annotation as `Kind by Author: "highlighted" — contents`.
+fn describe_annotation(annotation: &Annotation) -> String {
+    let mut description = annotation.kind.clone();
+    if let Some(author) = &annotation.author {
+        description.push_str(&format!(" by {}", author));
+    }
+    description.push(':');
+    if let Some(highlighted) = &annotation.highlighted {
+        description.push_str(&format!(" \"{}\"", highlighted));
+    }
+    if let Some(contents) = &annotation.contents {
+        let separator = if annotation.highlighted.is_some() { " — " } else { " " };
+        description.push_str(separator);
+        description.push_str(&normalize_whitespace(contents, TabMode::Collapse));
+    }
+    description
+}
+
+/// Rebuilds the text of every page from glyph positions and inserts each
+/// annotation as a bracketed line right after the last text line at or above
+/// its lower edge, so it is read next to the text it refers to. Annotations
+/// without a /Rect go at the end of their page.
+fn flatten_annotations(doc: &Document, annotations: &[Annotation]) -> Result<String, OutputError> {
+    let mut pages = Vec::new();
+
+    for page in doc.get_pages().into_keys() {
+        let chars = collect_positions(doc, [page])?;
+        let lines: Vec<(f64, String)> = chars
+            .chunk_by(|a, b| (a.y - b.y).abs() <= a.font_size * 0.5)
+            .map(|line| (line[0].y, chars_to_text(line)))
+            .collect();
+
+        // Each marker goes before the line at its index; the stable sort keeps
+        // annotations anchored to the same line in document order
+        let mut markers: Vec<(usize, String)> = annotations
+            .iter()
+            .filter(|annotation| annotation.page == page)
+            .map(|annotation| {
+                let index = match annotation.bottom {
+                    Some(bottom) => lines.iter().rposition(|(y, _)| *y >= bottom).map_or(0, |i| i + 1),
+                    None => lines.len(),
+                };
+                (index, format!("[{}]", describe_annotation(annotation)))
+            })
+            .collect();
+        markers.sort_by_key(|(index, _)| *index);
+
+        let mut markers = markers.into_iter().peekable();
+        let mut page_lines = Vec::new();
+        for (index, (_, line)) in lines.into_iter().enumerate() {
+            while let Some((_, marker)) = markers.next_if(|(at, _)| *at == index) {
+                page_lines.push(marker);
+            }
+            page_lines.push(line);
+        }
+        page_lines.extend(markers.map(|(_, marker)| marker));
+        pages.push(page_lines.join("\n"));
+    }
+
+    Ok(pages.join("\n\n"))
+}
+
+/// Rebuilds the text of every page from glyph positions, marking emphasis as
+/// Markdown. The body size is the size most of the document's text is set in;
+/// lines set at least 1.2 times larger become headings (`#` from twice the
+/// body size, `##` from 1.5 times, `###` below that), and runs in a font whose
+/// name says bold are wrapped in `**`.
+fn emphasis_markdown(doc: &Document) -> Result<String, OutputError> {
+    let mut pages = Vec::new();
+    for (page, page_id) in doc.get_pages() {
+        let bold: Vec<bool> = page_runs(doc, page_id)
+            .iter()
+            .map(|info| info.font.as_deref().is_some_and(is_bold_font))
+            .collect();
+        let chars = collect_positions(doc, [page])?;
+        pages.push((chars, bold));
+    }
+
+    let Some(body_size) = body_font_size(pages.iter().flat_map(|(chars, _)| chars)) else {
+        return Ok(String::new());
+    };
+
+    let text = pages
+        .iter()
+        .map(|(chars, bold)| {
+            let is_bold = |c: &PositionedChar| bold.get(c.run - 1).copied().unwrap_or(false);
+            chars
+                .chunk_by(|a, b| (a.y - b.y).abs() <= a.font_size * 0.5)
+                .map(|line| {
+                    let size = line.iter().map(|c| c.font_size).fold(0.0, f64::max);
+                    match size / body_size {
+                        ratio if ratio >= 2.0 => format!("# {}", chars_to_text(line).trim()),
+                        ratio if ratio >= 1.5 => format!("## {}", chars_to_text(line).trim()),
+                        ratio if ratio >= 1.2 => format!("### {}", chars_to_text(line).trim()),
+                        _ => emphasized_line(line, is_bold),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    Ok(text)
+}
+
+/// The size most of the given glyphs are set in, taken as the body text size.
+/// Sizes are bucketed to half a point so rounding noise does not split them.
+fn body_font_size<'a>(chars: impl IntoIterator<Item = &'a PositionedChar>) -> Option<f64> {
+    let mut sizes: HashMap<i64, usize> = HashMap::new();
+    for c in chars.into_iter().filter(|c| !c.text.trim().is_empty()) {
+        *sizes.entry((c.font_size * 2.0).round() as i64).or_default() += 1;
+    }
+    sizes.into_iter().max_by_key(|&(size, count)| (count, -size)).map(|(size, _)| size as f64 / 2.0)
+}
+
+/// Lines set at most this share of the body size can be footnotes.
+const FOOTNOTE_SIZE_RATIO: f64 = 0.9;
+
+/// Footnotes are only looked for in this share of the page, from the bottom.
+const FOOTNOTE_ZONE: f64 = 0.5;
+
+/// Rebuilds the text of every page from glyph positions with footnotes moved
+/// out of the body into a closing "Footnotes:" section, each labelled with its
+/// page as in `format_comments`. A page's footnotes are the lines in the lower
+/// part of the page that are set smaller than the body text and sit below all
+/// of it, and the first of them has to start with a note marker (a number,
+/// `*`, `†` and the like). A page where that is not the case, or that has no
+/// body-size text at all, is left as it is rather than risk moving body text.
+fn separate_footnotes(doc: &Document, labels: &HashMap<u32, String>) -> Result<String, OutputError> {
+    let marker = Regex::new(r"^(?:\d{1,3}|[*†‡§¶]{1,3}|[⁰¹²³⁴⁵⁶⁷⁸⁹]+)\D").unwrap();
+    let pages: Vec<(u32, lopdf::ObjectId, Vec<PositionedChar>)> = doc
+        .get_pages()
+        .into_iter()
+        .map(|(page, page_id)| Ok((page, page_id, collect_positions(doc, [page])?)))
+        .collect::<Result<_, OutputError>>()?;
+    let Some(body_size) = body_font_size(pages.iter().flat_map(|(_, _, chars)| chars)) else {
+        return Ok(String::new());
+    };
+
+    let mut bodies = Vec::new();
+    let mut footnotes = Vec::new();
+    for (page, page_id, chars) in &pages {
+        let lines: Vec<&[PositionedChar]> = chars.chunk_by(|a, b| (a.y - b.y).abs() <= a.font_size * 0.5).collect();
+        let zone_top = inherited_page_attribute(doc, *page_id, b"MediaBox")
+            .and_then(|b| b.as_array().ok())
+            .map(|b| b.iter().filter_map(|n| n.as_float().ok()).collect::<Vec<_>>())
+            .filter(|b| b.len() == 4)
+            .map_or(792.0 * FOOTNOTE_ZONE, |b| (b[1] + (b[3] - b[1]) * FOOTNOTE_ZONE as f32) as f64);
+        let is_small = |line: &[PositionedChar]| {
+            let size = line.iter().filter(|c| !c.text.trim().is_empty()).map(|c| c.font_size).fold(0.0, f64::max);
+            size <= body_size * FOOTNOTE_SIZE_RATIO
+        };
+        let lowest_body = lines
+            .iter()
+            .filter(|line| !is_small(line))
+            .map(|line| line[0].y)
+            .fold(f64::INFINITY, f64::min);
+
+        // Candidates from the top of the footnote area down; a bare number is
+        // more likely a page number and stays in the body
+        let mut notes: Vec<usize> = (0..lines.len())
+            .filter(|&i| is_small(lines[i]) && lines[i][0].y < lowest_body.min(zone_top))
+            .filter(|&i| !chars_to_text(lines[i]).trim().chars().all(|c| c.is_ascii_digit()))
+            .collect();
+        notes.sort_by(|&a, &b| lines[b][0].y.total_cmp(&lines[a][0].y));
+        let certain = lowest_body.is_finite()
+            && notes.first().is_some_and(|&first| marker.is_match(chars_to_text(lines[first]).trim()));
+        if !certain {
+            notes.clear();
+        }
+
+        for &i in &notes {
+            let text = chars_to_text(lines[i]).trim().to_string();
+            match footnotes.last_mut() {
+                Some((note_page, note)) if *note_page == *page && !marker.is_match(&text) => {
+                    *note = format!("{} {}", note, text);
+                }
+                _ => footnotes.push((*page, text)),
+            }
+        }
+        let body: Vec<String> = (0..lines.len())
+            .filter(|i| !notes.contains(i))
+            .map(|i| chars_to_text(lines[i]))
+            .collect();
+        bodies.push(body.join("\n"));
+    }
+
+    let mut text = bodies.join("\n\n");
+    if !footnotes.is_empty() {
+        text.push_str("\n\nFootnotes:");
+        for (page, note) in footnotes {
+            let page = labels.get(&page).cloned().unwrap_or_else(|| page.to_string());
+            text.push_str(&format!("\n\n[Page {}] {}", page, note));
+        }
+    }
+    Ok(text)
+}
+
+/// Whether a base font name names a bold weight, ignoring any subset prefix
+/// like `ABCDEF+`. Covers the usual Bold, Semibold, Black and Heavy names.
+fn is_bold_font(name: &str) -> bool {
+    let name = name.rsplit('+').next().unwrap_or(name).to_ascii_lowercase();
+    ["bold", "black", "heavy"].iter().any(|weight| name.contains(weight))
+}
+
+/// Turns one line of glyphs into text, wrapping each bold stretch in `**`.
+fn emphasized_line(line: &[PositionedChar], is_bold: impl Fn(&PositionedChar) -> bool) -> String {
+    marked_line(line, |c| is_bold(c).then_some(("**", "**")))
+}
+
+/// Turns one line of glyphs into text, wrapping each stretch of glyphs that
+/// `marker` gives the same opening and closing marks. Spaces at the edges of a
+/// stretch are kept outside the marks, where Markdown needs them.
+fn marked_line(line: &[PositionedChar], marker: impl Fn(&PositionedChar) -> Option<(&'static str, &'static str)>) -> String {
+    let mut text = String::new();
+    let mut previous: Option<&PositionedChar> = None;
+
+    for stretch in line.chunk_by(|a, b| marker(a) == marker(b)) {
+        let part = chars_to_text(stretch);
+        if let Some(prev) = previous {
+            let gap = stretch[0].x > prev.x + prev.width + prev.font_size * 0.1;
+            if gap && !text.ends_with(' ') && !part.starts_with(' ') {
+                text.push(' ');
+            }
+        }
+        previous = stretch.last();
+
+        let trimmed = part.trim();
+        let Some((open, close)) = marker(&stretch[0]).filter(|_| !trimmed.is_empty()) else {
+            text.push_str(&part);
+            continue;
+        };
+        let leading = part.len() - part.trim_start().len();
+        let trailing = part.trim_end().len();
+        text.push_str(&part[..leading]);
+        text.push_str(open);
+        text.push_str(trimmed);
+        text.push_str(close);
+        text.push_str(&part[trailing..]);
+    }
+
+    text
+}
+
+/// A `--crop` box in PDF units, on one page or on every page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CropRegion {
+    page: Option<u32>,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+impl CropRegion {
+    fn contains(&self, (x, y): (f64, f64)) -> bool {
+        x >= self.x && x <= self.x + self.width && y >= self.y && y <= self.y + self.height
+    }
+}
+
+fn parse_crop(value: &str) -> Result<CropRegion, String> {
+    let (page, rect) = match value.split_once(':') {
+        Some((page, rect)) => {
+            let page = page.trim().parse::<u32>().ok().filter(|&page| page > 0);
+            (Some(page.ok_or_else(|| format!("invalid page number in '{}'", value))?), rect)
+        }
+        None => (None, value),
+    };
+    let numbers: Vec<f64> = rect
+        .split(',')
+        .map(|n| n.trim().parse::<f64>().ok().filter(|n| n.is_finite()))
+        .collect::<Option<_>>()
+        .filter(|numbers: &Vec<f64>| numbers.len() == 4)
+        .ok_or_else(|| format!("invalid crop box '{}' (expected X,Y,W,H, optionally after PAGE:)", value))?;
+    if numbers[2] <= 0.0 || numbers[3] <= 0.0 {
+        return Err(format!("crop box '{}' needs a positive width and height", value));
+    }
+    Ok(CropRegion {
+        page,
+        x: numbers[0],
+        y: numbers[1],
+        width: numbers[2],
+        height: numbers[3],
+    })
+}
+
+/// Rebuilds the text of every page from the glyphs whose centers fall inside
+/// one of its `--crop` boxes. A page's own boxes replace the boxes without a
+/// page; pages left with no box are skipped.
+fn crop_text(doc: &Document, regions: &[CropRegion]) -> Result<String, OutputError> {
+    let mut pages = Vec::new();
+    for page in doc.get_pages().into_keys() {
+        let mut boxes: Vec<&CropRegion> = regions.iter().filter(|region| region.page == Some(page)).collect();
+        if boxes.is_empty() {
+            boxes = regions.iter().filter(|region| region.page.is_none()).collect();
+        }
+        if boxes.is_empty() {
+            continue;
+        }
+        let chars = collect_positions(doc, [page])?;
+        let kept = chars.iter().filter(|c| boxes.iter().any(|region| region.contains(c.center())));
+        pages.push(chars_to_text(kept));
+    }
+    Ok(pages.join("\n\n"))
+}
+
+/// Glyphs set at most this share of their line's size can be superscripts or
+/// subscripts.
+const SCRIPT_SIZE_RATIO: f64 = 0.9;
+
+/// Rebuilds the text of every page from glyph positions, marking superscripts
+/// as `^{...}` and subscripts as `_{...}`. Within a line, a glyph set smaller
+/// than the line's largest size is a superscript when its baseline is more
+/// than 0.15 of that size above the baseline of the full-size glyphs, and a
+/// subscript when it is more than 0.1 of it below.
+fn mark_super_sub(doc: &Document) -> Result<String, OutputError> {
+    let mut pages = Vec::new();
+    for page in doc.get_pages().into_keys() {
+        let chars = collect_positions(doc, [page])?;
+        // Compare against the larger glyph, so a raised small glyph still joins
+        // the line it is raised from
+        let lines: Vec<String> = chars
+            .chunk_by(|a, b| (a.y - b.y).abs() <= a.font_size.max(b.font_size) * 0.5)
+            .map(super_sub_line)
+            .collect();
+        pages.push(lines.join("\n"));
+    }
+    Ok(pages.join("\n\n"))
+}
+
+fn super_sub_line(line: &[PositionedChar]) -> String {
+    let visible = |c: &&PositionedChar| !c.text.trim().is_empty();
+    let size = line.iter().filter(visible).map(|c| c.font_size).fold(0.0, f64::max);
+    let Some(baseline) = line.iter().filter(visible).find(|c| c.font_size > size * SCRIPT_SIZE_RATIO).map(|c| c.y) else {
+        return chars_to_text(line);
+    };
+    marked_line(line, |c| {
+        if c.text.trim().is_empty() || c.font_size > size * SCRIPT_SIZE_RATIO {
+            return None;
+        }
+        match c.y - baseline {
+            shift if shift > size * 0.15 => Some(("^{", "}")),
+            shift if shift < -size * 0.1 => Some(("_{", "}")),
+            _ => None,
+        }
+    })
+}
+
+/// A file embedded in the document through the /EmbeddedFiles name tree.
+#[derive(Debug)]
+struct Attachment {
+    name: String,
+    data: Vec<u8>,
+}
+
+/// Reads the embedded files listed in the catalog's /EmbeddedFiles name tree.
+/// The file name comes from the file specification's /UF or /F entry, falling
+/// back to the name tree key.
+fn read_attachments(doc: &Document) -> Result<Vec<Attachment>, lopdf::Error> {
+    let Ok(tree) = doc
+        .catalog()?
+        .get_deref(b"Names", doc)
+        .and_then(Object::as_dict)
+        .and_then(|names| names.get_deref(b"EmbeddedFiles", doc))
+        .and_then(Object::as_dict)
+    else {
+        return Ok(Vec::new());
+    };
+
+    let mut attachments = Vec::new();
+    for (key, spec) in name_tree_entries(doc, tree) {
+        let Ok(spec) = spec.as_dict() else {
+            continue;
+        };
+        let name = [b"UF".as_slice(), b"F"]
+            .iter()
+            .find_map(|field| spec.get_deref(field, doc).and_then(Object::as_str).ok())
+            .unwrap_or(key);
+        let Ok(stream) = spec
+            .get_deref(b"EF", doc)
+            .and_then(Object::as_dict)
+            .and_then(|ef| ef.get_deref(b"F", doc))
+            .and_then(Object::as_stream)
+        else {
+            continue;
+        };
+        attachments.push(Attachment {
+            name: decode_pdf_string(name),
+            data: stream.get_plain_content()?,
+        });
+    }
+
+    Ok(attachments)
+}
+
+/// Whether the document is a PDF portfolio (a package of embedded files shown
+/// through a /Collection), rather than a document that happens to carry
+/// attachments.
+fn is_portfolio(doc: &Document) -> bool {
+    doc.catalog().is_ok_and(|catalog| catalog.has(b"Collection"))
+}
+
+/// Converts every embedded PDF of a portfolio into its own numbered output
+/// file (`name_doc01.txt`, ...) and returns each document's name with the path
+/// written. Embedded files that are not PDFs are skipped, and a member that
+/// cannot be read is reported as a warning without stopping the others.
+fn expand_portfolio(
+    doc: &Document,
+    output_path: &Path,
+    options: &ProcessingOptions,
+    overwrite: &mut OverwriteGuard,
+    warnings: &mut Warnings,
+) -> Result<Vec<(String, PathBuf)>, Box<dyn std::error::Error>> {
+    let members: Vec<Attachment> = read_attachments(doc)?
+        .into_iter()
+        .filter(|attachment| attachment.data.windows(5).take(1024).any(|window| window == b"%PDF-"))
+        .collect();
+
+    let mut written = Vec::new();
+    for (index, member) in members.iter().enumerate() {
+        let path = numbered_output_path(output_path, &format!("_doc{:02}", index + 1));
+        if !overwrite.allows(&path) {
+            continue;
+        }
+        let text = Document::load_mem(&member.data)
+            .and_then(|embedded| unlock_document(embedded, None))
+            .map_err(OutputError::from)
+            .and_then(|(embedded, _)| extract_document_text(&embedded));
+        match text {
+            Ok(text) => {
+                write_output(&path, &process_extracted_text(&text, options), options)?;
+                written.push((member.name.clone(), path));
+            }
+            Err(e) => warnings.warn(None, "portfolio_member_failed", format!("could not convert '{}': {}", member.name, e)),
+        }
+    }
+    Ok(written)
+}
+
+/// Writes attachments into `dir` and returns the file names used. Names are
+/// reduced to their last path component so an attachment cannot be written
+/// outside `dir`, and a name that is already taken gets a `_2`, `_3`, ...
+/// suffix before its extension.
+fn save_attachments(attachments: &[Attachment], dir: &Path, options: &ProcessingOptions) -> std::io::Result<Vec<String>> {
+    create_output_dir(dir, options)?;
+
+    let mut saved: Vec<String> = Vec::new();
+    for attachment in attachments {
+        let base = attachment
+            .name
+            .rsplit(['/', '\\'])
+            .next()
+            .filter(|name| !matches!(*name, "" | "." | ".."))
+            .unwrap_or("attachment");
+        let (stem, extension) = match base.rfind('.').filter(|&dot| dot > 0) {
+            Some(dot) => base.split_at(dot),
+            None => (base, ""),
+        };
+
+        let mut name = base.to_string();
+        let mut counter = 1;
+        while saved.contains(&name) || dir.join(&name).exists() {
+            counter += 1;
+            name = format!("{}_{}{}", stem, counter, extension);
+        }
+        write_file(&dir.join(&name), &attachment.data, options)?;
+        saved.push(name);
+    }
+
+    Ok(saved)
+}
+
+/// Extracts text starting at the page a named destination points to, either
+/// through the end of the document or, with `only_that_page`, just that page.
+fn extract_from_destination(
+    doc: &Document,
+    name: &str,
+    only_that_page: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let start = resolve_named_destination(doc, name.as_bytes())
+        .ok_or_else(|| format!("named destination '{}' not found", name))?;
+    let end = if only_that_page { start } else { doc.get_pages().len() as u32 };
+
+    Ok(extract_pages_text(doc, start..=end)?)
+}
+
+/// Decodes a PDF text string, which is either UTF-16BE with a byte order mark
+/// or a single-byte encoding (treated as Latin-1).
+fn decode_pdf_string(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Returns the title and target page of each top-level outline entry, ordered
+/// by page. Entries without a resolvable page are skipped, and when several
+/// entries point at the same page only the first is kept.
+fn outline_chapters(doc: &Document) -> Vec<(String, u32)> {
+    let mut chapters = Vec::new();
+    let Some(outlines) = doc
+        .catalog()
+        .and_then(|catalog| catalog.get_deref(b"Outlines", doc))
+        .and_then(Object::as_dict)
+        .ok()
+    else {
+        return chapters;
+    };
+
+    let mut seen = HashSet::new();
+    let mut next = outlines.get(b"First").and_then(Object::as_reference).ok();
+    while let Some(id) = next {
+        // Guard against malformed outlines whose /Next links form a cycle
+        if !seen.insert(id) {
+            break;
+        }
+        let Ok(item) = doc.get_dictionary(id) else {
+            break;
+        };
+
+        if let Some(page) = outline_item_page(doc, item) {
+            let title = item
+                .get_deref(b"Title", doc)
+                .and_then(Object::as_str)
+                .map(decode_pdf_string)
+                .unwrap_or_default();
+            chapters.push((title, page));
+        }
+        next = item.get(b"Next").and_then(Object::as_reference).ok();
+    }
+
+    chapters.sort_by_key(|&(_, page)| page);
+    chapters.dedup_by_key(|&mut (_, page)| page);
+    chapters
+}
+
+/// Resolves the page an outline item points to, either through its /Dest entry
+/// or a GoTo action's /D entry.
+fn outline_item_page(doc: &Document, item: &Dictionary) -> Option<u32> {
+    let dest = match item.get_deref(b"Dest", doc) {
+        Ok(dest) => dest,
+        Err(_) => item
+            .get_deref(b"A", doc)
+            .and_then(Object::as_dict)
+            .and_then(|action| action.get_deref(b"D", doc))
+            .ok()?,
+    };
+
+    match dest {
+        Object::String(name, _) | Object::Name(name) => resolve_named_destination(doc, name),
+        explicit => destination_page(doc, explicit),
+    }
+}
+
+/// Builds the path for one part of a split output by inserting `suffix` before
+/// the extension, e.g. `report.txt` with `_ch01` becomes `report_ch01.txt`.
+fn numbered_output_path(output_path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = output_path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    if let Some(ext) = output_path.extension() {
+        file_name.push(".");
+        file_name.push(ext);
+    }
+    output_path.with_file_name(file_name)
+}
+
+/// The default output path: the input's file stem with a `.txt` extension, in
+/// the current directory. Works on the raw `OsStr` so non-UTF-8 names survive.
+/// Leading dots are dropped so a hidden `.pdf` doesn't produce a hidden
+/// output, and a name that is nothing but dots falls back to `output.txt`.
+/// Inputs that name a directory (`..`, `/`, `docs/`) are an error.
+fn default_output_path(input_path: &Path) -> Result<PathBuf, String> {
+    let looks_like_dir = input_path
+        .as_os_str()
+        .to_string_lossy()
+        .ends_with(std::path::is_separator)
+        || input_path.is_dir();
+    let stem = match input_path.file_stem() {
+        Some(stem) if !looks_like_dir => stem,
+        _ => {
+            return Err(format!(
+                "'{}' is a directory, not a PDF file; pass a file or use --output",
+                input_path.display()
+            ))
+        }
+    };
+    let mut file_name = match stem.to_str() {
+        Some(stem) if stem.trim_start_matches('.').is_empty() => OsString::from("output"),
+        Some(stem) => OsString::from(stem.trim_start_matches('.')),
+        None => stem.to_os_string(),
+    };
+    file_name.push(".txt");
+    Ok(PathBuf::from(file_name))
+}
+
+/// Writes one processed file per top-level outline entry. Pages before the
+/// first entry are included in the first chapter. Returns `None` when the
+/// document has no usable outline.
+fn split_by_heading(
+    doc: &Document,
+    output_path: &Path,
+    options: &ProcessingOptions,
+    overwrite: &mut OverwriteGuard,
+) -> Result<Option<Vec<PathBuf>>, Box<dyn std::error::Error>> {
+    let chapters = outline_chapters(doc);
+    if chapters.is_empty() {
+        return Ok(None);
+    }
+
+    let page_count = doc.get_pages().len() as u32;
+    let groups: Vec<RangeInclusive<u32>> = chapters
+        .iter()
+        .enumerate()
+        .map(|(index, (_, start))| {
+            let first = if index == 0 { 1 } else { *start };
+            let last = chapters.get(index + 1).map_or(page_count, |(_, next)| next - 1);
+            first..=last
+        })
+        .collect();
+
+    Ok(Some(write_page_groups(doc, &groups, output_path, "ch", options, overwrite)?))
+}
+
+/// Writes consecutive groups of `pages_per_file` pages to numbered part files.
+fn split_by_pages(
+    doc: &Document,
+    output_path: &Path,
+    pages_per_file: u32,
+    options: &ProcessingOptions,
+    overwrite: &mut OverwriteGuard,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let page_count = doc.get_pages().len() as u32;
+    let groups: Vec<RangeInclusive<u32>> = (1..=page_count)
+        .step_by(pages_per_file as usize)
+        .map(|first| first..=(first + pages_per_file - 1).min(page_count))
+        .collect();
+
+    write_page_groups(doc, &groups, output_path, "part", options, overwrite)
+}
+
+/// Extracts and processes each group of pages into its own file, numbered with
+/// `label` (`name_ch01.txt`, `name_part01.txt`, ...), and returns the paths
+/// written. Files the user chose not to overwrite are left out.
+fn write_page_groups(
+    doc: &Document,
+    groups: &[RangeInclusive<u32>],
+    output_path: &Path,
+    label: &str,
+    options: &ProcessingOptions,
+    overwrite: &mut OverwriteGuard,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut written = Vec::new();
+    for (index, pages) in groups.iter().enumerate() {
+        let path = numbered_output_path(output_path, &format!("_{}{:02}", label, index + 1));
+        if !overwrite.allows(&path) {
+            continue;
+        }
+        let text = extract_pages_text(doc, pages.clone())?;
+        write_output(&path, &process_extracted_text(&text, options), options)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Writes `content` as numbered part files (`name_part01.txt`, ...) that each
+/// stay within `max_bytes` once wrapped in the banner and footer and given
+/// their final line endings. Returns the paths written.
+fn write_size_limited_parts(
+    content: &str,
+    output_path: &Path,
+    max_bytes: usize,
+    options: &ProcessingOptions,
+    overwrite: &mut OverwriteGuard,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let newline_bytes = with_line_endings("\n", options.newline).len();
+    let wrapped_size = |text: &str, part| {
+        let options = ProcessingOptions { part: Some(part), ..options.clone() };
+        with_line_endings(&wrap_content(text, &options), options.newline).len()
+    };
+
+    // The banner grows with the number of digits in the part count, so size
+    // it for the largest count of each width until the split fits
+    let mut digits = 1;
+    let parts = loop {
+        let widest = 10usize.pow(digits) - 1;
+        let overhead = wrapped_size("", (widest, widest));
+        if overhead >= max_bytes {
+            return Err(format!("{} bytes leaves no room for content after the banner and footer", max_bytes).into());
+        }
+        let parts = split_by_size(content, max_bytes - overhead, newline_bytes);
+        if parts.len() <= widest {
+            break parts;
+        }
+        digits += 1;
+    };
+
+    let mut written = Vec::new();
+    for (index, part) in parts.iter().enumerate() {
+        let path = numbered_output_path(output_path, &format!("_part{:02}", index + 1));
+        if !overwrite.allows(&path) {
+            continue;
+        }
+        let options = ProcessingOptions { part: Some((index + 1, parts.len())), ..options.clone() };
+        write_output(&path, &wrap_content(part, &options), &options)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Splits text into pieces of at most `budget` bytes, counting each line
+/// break as `newline_bytes`. Pieces end at line breaks where possible; a line
+/// that is too long by itself is broken between words, and a word that is too
+/// long between characters.
+fn split_by_size(text: &str, budget: usize, newline_bytes: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut current_bytes = 0;
+
+    for line in text.split('\n') {
+        for piece in break_line(line, budget) {
+            let needed = if current.is_empty() { piece.len() } else { newline_bytes + piece.len() };
+            if !current.is_empty() && current_bytes + needed > budget {
+                parts.push(std::mem::take(&mut current));
+                current_bytes = 0;
+            }
+            if !current.is_empty() {
+                current.push('\n');
+                current_bytes += newline_bytes;
+            }
+            current_bytes += piece.len();
+            current.push_str(piece);
+        }
+    }
+    if !current.is_empty() || parts.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// Breaks one line into pieces of at most `budget` bytes, between words where
+/// it can. The spaces at a break are dropped.
+fn break_line(line: &str, budget: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
+    while rest.len() > budget {
+        let mut end = budget;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        // Prefer the last space that still fits; otherwise cut inside the word
+        let space = rest
+            .char_indices()
+            .take_while(|&(i, _)| i <= budget)
+            .filter(|&(i, c)| c == ' ' && i > 0)
+            .last();
+        let cut = match space {
+            Some((i, _)) => i,
+            None => end.max(rest.chars().next().map_or(1, char::len_utf8)),
+        };
+        pieces.push(rest[..cut].trim_end());
+        rest = rest[cut..].trim_start();
+    }
+    pieces.push(rest);
+    pieces
+}
+
+/// Collects the key/value pairs of a name tree, descending into its kids.
+fn name_tree_entries<'a>(doc: &'a Document, node: &'a Dictionary) -> Vec<(&'a [u8], &'a Object)> {
+    let mut entries = Vec::new();
+
+    if let Ok(names) = node.get(b"Names").and_then(Object::as_array) {
+        for pair in names.chunks_exact(2) {
+            if let (Ok(key), Ok((_, value))) = (pair[0].as_str(), doc.dereference(&pair[1])) {
+                entries.push((key, value));
+            }
+        }
+    }
+
+    if let Ok(kids) = node.get(b"Kids").and_then(Object::as_array) {
+        for kid in kids {
+            if let Ok(kid) = doc.dereference(kid).and_then(|(_, kid)| kid.as_dict()) {
+                entries.extend(name_tree_entries(doc, kid));
+            }
+        }
+    }
+
+    entries
+}
+
+/// Resolves a named destination to its 1-based page number, checking both the
+/// catalog's /Dests dictionary and the /Dests name tree under /Names.
+fn resolve_named_destination(doc: &Document, name: &[u8]) -> Option<u32> {
+    let catalog = doc.catalog().ok()?;
+
+    let from_dests = catalog
+        .get_deref(b"Dests", doc)
+        .and_then(Object::as_dict)
+        .and_then(|dests| dests.get_deref(name, doc))
+        .ok();
+    let from_names = || {
+        let tree = catalog
+            .get_deref(b"Names", doc)
+            .and_then(Object::as_dict)
+            .and_then(|names| names.get_deref(b"Dests", doc))
+            .and_then(Object::as_dict)
+            .ok()?;
+        name_tree_entries(doc, tree)
+            .into_iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    };
+
+    destination_page(doc, from_dests.or_else(from_names)?)
+}
+
+/// Finds the page number targeted by an explicit destination, which is either
+/// an array starting with the page reference or a dictionary whose /D entry
+/// is such an array.
+fn destination_page(doc: &Document, dest: &Object) -> Option<u32> {
+    let array = match dest {
+        Object::Array(array) => array,
+        Object::Dictionary(dict) => dict.get_deref(b"D", doc).and_then(Object::as_array).ok()?,
+        _ => return None,
+    };
+    let page_id = array.first()?.as_reference().ok()?;
+
+    doc.get_pages()
+        .into_iter()
+        .find(|(_, id)| *id == page_id)
+        .map(|(number, _)| number)
+}
+
+/// Looks up a page attribute, following /Parent links for attributes such as
+/// /Rotate and /MediaBox that can be inherited from the page tree.
+fn inherited_page_attribute<'a>(doc: &'a Document, page_id: lopdf::ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    // Bound the walk in case of a cyclic page tree
+    for _ in 0..64 {
+        if let Ok(value) = node.get_deref(key, doc) {
+            return Some(value);
+        }
+        node = node.get_deref(b"Parent", doc).and_then(Object::as_dict).ok()?;
+    }
+    None
+}
+
+/// Runs the per-page checks after every extraction: pages without text,
+/// rotated pages, fonts without a ToUnicode map, and control characters in
+/// the extracted text. The findings go to `--warn-log` or else to stderr.
+fn check_document(doc: &Document, text: &str, warnings: &mut Warnings) {
+    for (page, names) in missing_tounicode_pages(doc, &document_fonts(doc)) {
+        warnings.warn(
+            Some(page),
+            "missing_tounicode",
+            format!("fonts without a ToUnicode map, text may be garbled: {}", names.join(", ")),
+        );
+    }
+
+    let control_chars = text
+        .chars()
+        .filter(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+        .count();
+    if control_chars > 0 {
+        warnings.warn(
+            None,
+            "control_chars",
+            format!("{} control characters in extracted text", control_chars),
+        );
+    }
+
+    for (page, page_id) in doc.get_pages() {
+        let rotation = inherited_page_attribute(doc, page_id, b"Rotate").and_then(|r| r.as_i64().ok());
+        if let Some(rotation) = rotation.filter(|r| r % 360 != 0) {
+            warnings.warn(Some(page), "rotated_page", format!("page is rotated by {} degrees", rotation));
+        }
+
+        match extract_pages_text(doc, [page]) {
+            Ok(page_text) if page_text.trim().is_empty() => {
+                warnings.warn(Some(page), "empty_page", "no text extracted from page");
+            }
+            Ok(_) => {}
+            Err(e) => warnings.warn(Some(page), "page_failed", format!("could not extract page: {}", e)),
+        }
+    }
+}
+
+/// Reads the page count from the page tree without extracting any text.
+fn count_pages(doc: &Document) -> usize {
+    doc.get_pages().len()
+}
+
+/// Text repeated on each page of the bundled `--benchmark` sample.
+const BENCHMARK_SAMPLE_PAGE: &str = "PDFBot benchmark sample\n\
+    The quick brown fox jumps over the lazy dog. Pack my box with five dozen liquor jugs.\n\
+    Sphinx of black quartz, judge my vow. How vexingly quick daft zebras jump!\n\
+    \n\
+    1. First item    2. Second item    3. Third item\n\
+    Totals: 1,234.56  7,890.12  3,456.78";
+
+/// Mean, median and 95th percentile of a set of timings, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timing {
+    mean: f64,
+    median: f64,
+    p95: f64,
+}
+
+impl Timing {
+    fn from_samples(mut samples: Vec<f64>) -> Timing {
+        samples.sort_by(f64::total_cmp);
+        let n = samples.len();
+        let median = if n.is_multiple_of(2) {
+            (samples[n / 2 - 1] + samples[n / 2]) / 2.0
+        } else {
+            samples[n / 2]
+        };
+        // Nearest-rank percentile
+        let p95 = samples[(n * 95).div_ceil(100) - 1];
+        Timing {
+            mean: samples.iter().sum::<f64>() / n as f64,
+            median,
+            p95,
+        }
+    }
+}
+
+/// Extracts and processes the whole document `runs` times, timing the two
+/// stages separately.
+fn benchmark(doc: &Document, runs: usize, options: &ProcessingOptions) -> Result<(Timing, Timing), OutputError> {
+    let mut extraction = Vec::with_capacity(runs);
+    let mut processing = Vec::with_capacity(runs);
+    for _ in 0..runs {
+        let started = std::time::Instant::now();
+        let text = extract_document_text(doc)?;
+        extraction.push(started.elapsed().as_secs_f64() * 1000.0);
+
+        let started = std::time::Instant::now();
+        std::hint::black_box(process_extracted_text(&text, options));
+        processing.push(started.elapsed().as_secs_f64() * 1000.0);
+    }
+    Ok((Timing::from_samples(extraction), Timing::from_samples(processing)))
+}
+
+/// Serializes a JSON report, indented with `--pretty`.
+fn render_json(value: &serde_json::Value, pretty: bool) -> String {
+    if pretty {
+        serde_json::to_string_pretty(value).unwrap_or_default()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Runs `--benchmark` and prints the report as text, or as JSON when `json`
+/// is set (its value saying whether to pretty-print).
+fn print_benchmark(doc: &Document, source: &str, runs: usize, options: &ProcessingOptions, json: Option<bool>) {
+    let (extraction, processing) = match benchmark(doc, runs, options) {
+        Ok(timings) => timings,
+        Err(e) => {
+            fail(Failure::Extraction, Some(source), format!("Error extracting text from PDF: {}", e));
+        }
+    };
+
+    if let Some(pretty) = json {
+        let timing = |t: Timing| json!({ "mean_ms": t.mean, "median_ms": t.median, "p95_ms": t.p95 });
+        let report = json!({
+            "input": source,
+            "pages": count_pages(doc),
+            "runs": runs,
+            "extraction": timing(extraction),
+            "processing": timing(processing),
+        });
+        println!("{}", render_json(&report, pretty));
+    } else {
+        println!("⏱️ Benchmark of '{}' ({} pages, {} runs)", source, count_pages(doc), runs);
+        for (stage, t) in [("extraction", extraction), ("processing", processing)] {
+            println!(
+                "  {}: mean {:.2} ms, median {:.2} ms, p95 {:.2} ms",
+                stage, t.mean, t.median, t.p95
+            );
+        }
+    }
+}
+
+/// Rebuilds each page's text from glyph positions alone, the way the layout
+/// and annotation features see it, with a blank line between pages.
+fn positional_text(doc: &Document) -> Result<String, OutputError> {
+    let mut pages = Vec::new();
+    for page in doc.get_pages().into_keys() {
+        pages.push(chars_to_text(&collect_positions(doc, [page])?));
+    }
+    Ok(pages.join("\n\n"))
+}
+
+/// How the default pdf-extract text and `positional_text` differ on a document.
+#[derive(Debug)]
+struct BackendComparison {
+    plain_chars: usize,
+    positional_chars: usize,
+    plain_ms: f64,
+    positional_ms: f64,
+    /// Non-blank lines compared, the longer of the two outputs.
+    lines: usize,
+    differing_lines: usize,
+    /// 1-based non-blank line number and the two versions of the first line
+    /// that differs.
+    first_difference: Option<(usize, String, String)>,
+}
+
+/// Runs both extractions once and compares their non-blank lines with
+/// whitespace normalized, so spacing differences alone do not count.
+fn compare_backends(doc: &Document) -> Result<BackendComparison, OutputError> {
+    let started = std::time::Instant::now();
+    let plain = extract_document_text(doc)?;
+    let plain_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let started = std::time::Instant::now();
+    let positional = positional_text(doc)?;
+    let positional_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let lines = |text: &str| -> Vec<String> {
+        text.lines()
+            .map(|line| normalize_whitespace(line, TabMode::Collapse))
+            .filter(|line| !line.is_empty())
+            .collect()
+    };
+    let (plain_lines, positional_lines) = (lines(&plain), lines(&positional));
+    let line_count = plain_lines.len().max(positional_lines.len());
+    let differs = |i: &usize| plain_lines.get(*i) != positional_lines.get(*i);
+
+    Ok(BackendComparison {
+        plain_chars: plain.chars().count(),
+        positional_chars: positional.chars().count(),
+        plain_ms,
+        positional_ms,
+        lines: line_count,
+        differing_lines: (0..line_count).filter(differs).count(),
+        first_difference: (0..line_count).find(differs).map(|i| {
+            let line = |lines: &[String]| lines.get(i).cloned().unwrap_or_default();
+            (i + 1, line(&plain_lines), line(&positional_lines))
+        }),
+    })
+}
+
+/// Prints the `--compare-backends` report as text, or as JSON when `json` is set.
+fn print_backend_comparison(doc: &Document, source: &str, json: Option<bool>) {
+    let comparison = compare_backends(doc).unwrap_or_else(|e| {
+        fail(Failure::Extraction, Some(source), format!("Error extracting text from PDF: {}", e));
+    });
+    let delta = comparison.positional_chars as i64 - comparison.plain_chars as i64;
+
+    if let Some(pretty) = json {
+        let report = json!({
+            "input": source,
+            "plain": { "chars": comparison.plain_chars, "ms": comparison.plain_ms },
+            "positional": { "chars": comparison.positional_chars, "ms": comparison.positional_ms },
+            "char_delta": delta,
+            "lines": comparison.lines,
+            "differing_lines": comparison.differing_lines,
+            "first_difference": comparison.first_difference.map(|(line, plain, positional)| {
+                json!({ "line": line, "plain": plain, "positional": positional })
+            }),
+        });
+        println!("{}", render_json(&report, pretty));
+        return;
+    }
+
+    println!("Backend comparison for '{}':", source);
+    println!("  plain:      {} chars in {:.2} ms", comparison.plain_chars, comparison.plain_ms);
+    println!(
+        "  positional: {} chars in {:.2} ms ({:+} chars)",
+        comparison.positional_chars, comparison.positional_ms, delta
+    );
+    println!("  differing lines: {} of {}", comparison.differing_lines, comparison.lines);
+    if let Some((line, plain, positional)) = &comparison.first_difference {
+        let sample = |text: &str| text.chars().take(80).collect::<String>();
+        println!("  first difference at line {}:", line);
+        println!("    plain:      {:?}", sample(plain));
+        println!("    positional: {:?}", sample(positional));
+    }
+}
+
+const XMP_PDFAID_NS: &str = "http://www.aiim.org/pdfa/ns/id/";
+const XMP_DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+const XMP_RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// PDF/A identification and Dublin Core properties from an XMP packet.
+#[derive(Debug, Default, PartialEq)]
+struct XmpInfo {
+    /// `pdfaid:part`, e.g. "1" for PDF/A-1.
+    pdfa_part: Option<String>,
+    /// `pdfaid:conformance`, e.g. "B".
+    pdfa_conformance: Option<String>,
+    /// Dublin Core properties in document order, each with its values (the
+    /// items of an rdf:Alt, rdf:Bag or rdf:Seq, or the plain text).
+    dublin_core: Vec<(String, Vec<String>)>,
+}
+
+/// Reads the XMP packet referenced by the catalog's /Metadata entry. Returns
+/// `None` when there is none, and an error when it is not well-formed XML.
+fn read_xmp(doc: &Document) -> Result<Option<XmpInfo>, String> {
+    let Ok(stream) = doc
+        .catalog()
+        .and_then(|catalog| catalog.get_deref(b"Metadata", doc))
+        .and_then(Object::as_stream)
+    else {
+        return Ok(None);
+    };
+    let data = stream.get_plain_content().map_err(|e| e.to_string())?;
+    let xml = String::from_utf8_lossy(&data);
+    parse_xmp(xml.trim_start_matches('\u{feff}')).map(Some)
+}
+
+fn parse_xmp(xml: &str) -> Result<XmpInfo, String> {
+    let tree = roxmltree::Document::parse(xml).map_err(|e| e.to_string())?;
+    let mut info = XmpInfo::default();
+    let mut record = |namespace: Option<&str>, name: &str, values: Vec<String>| match namespace {
+        Some(XMP_PDFAID_NS) if name == "part" => info.pdfa_part = values.into_iter().next(),
+        Some(XMP_PDFAID_NS) if name == "conformance" => info.pdfa_conformance = values.into_iter().next(),
+        Some(XMP_DC_NS) if !values.is_empty() && !info.dublin_core.iter().any(|(n, _)| n == name) => {
+            info.dublin_core.push((name.to_string(), values));
+        }
+        _ => {}
+    };
+
+    for node in tree.descendants().filter(|node| node.is_element()) {
+        // Simple properties may also be written as attributes of rdf:Description
+        for attribute in node.attributes() {
+            record(attribute.namespace(), attribute.name(), vec![attribute.value().trim().to_string()]);
+        }
+
+        let tag = node.tag_name();
+        if !matches!(tag.namespace(), Some(XMP_PDFAID_NS | XMP_DC_NS)) {
+            continue;
+        }
+        let items: Vec<String> = node
+            .descendants()
+            .filter(|item| item.tag_name().namespace() == Some(XMP_RDF_NS) && item.tag_name().name() == "li")
+            .filter_map(|item| item.text().map(str::trim).filter(|text| !text.is_empty()))
+            .map(str::to_string)
+            .collect();
+        let values = if items.is_empty() {
+            node.text().map(str::trim).filter(|text| !text.is_empty()).map(str::to_string).into_iter().collect()
+        } else {
+            items
+        };
+        record(tag.namespace(), tag.name(), values);
+    }
+
+    Ok(info)
+}
+
+/// Prints the `--xmp` report as text, or as JSON when `json` is set (its
+/// value saying whether to pretty-print).
+fn print_xmp(doc: &Document, source: &str, json: Option<bool>, warnings: &mut Warnings) {
+    let xmp = read_xmp(doc).unwrap_or_else(|e| {
+        warnings.warn(None, "invalid_xmp", format!("could not parse XMP metadata: {}", e));
+        None
+    });
+
+    if let Some(pretty) = json {
+        let xmp = xmp.map(|info| {
+            let pdfa = info
+                .pdfa_part
+                .as_ref()
+                .map(|part| json!({ "part": part, "conformance": info.pdfa_conformance }));
+            let dublin_core: serde_json::Map<String, serde_json::Value> =
+                info.dublin_core.into_iter().map(|(name, values)| (name, json!(values))).collect();
+            json!({ "pdfa": pdfa, "dublin_core": dublin_core })
+        });
+        println!("{}", render_json(&json!({ "input": source, "xmp": xmp }), pretty));
+        return;
+    }
+
+    let Some(info) = xmp else {
+        println!("No XMP metadata in '{}'", source);
+        return;
+    };
+    println!("XMP metadata for '{}':", source);
+    match &info.pdfa_part {
+        Some(part) => println!("  PDF/A: PDF/A-{}{}", part, info.pdfa_conformance.as_deref().unwrap_or_default()),
+        None => println!("  PDF/A: not declared"),
+    }
+    for (name, values) in &info.dublin_core {
+        println!("  dc:{}: {}", name, values.join("; "));
+    }
+}
+
+/// The document information entries `info` lists, with their JSON names.
+const INFO_ENTRIES: [(&str, &[u8]); 5] = [
+    ("title", b"Title"),
+    ("author", b"Author"),
+    ("subject", b"Subject"),
+    ("creator", b"Creator"),
+    ("producer", b"Producer"),
+];
+
+/// Prints the `info` summary of a document, its page count, metadata,
+/// structure and fonts, as text or as JSON when `json` is set.
+fn print_info(doc: &Document, source: &str, json: Option<bool>) {
+    let fonts = document_fonts(doc);
+    let without_unicode = fonts.iter().filter(|font| font.lacks_unicode()).count();
+    let metadata: Vec<(&str, String)> = INFO_ENTRIES
+        .iter()
+        .filter_map(|&(name, key)| info_entry(doc, key).map(|value| (name, value)))
+        .collect();
+    let tagged = doc.catalog().is_ok_and(|catalog| catalog.has(b"StructTreeRoot"));
+
+    if let Some(pretty) = json {
+        let metadata: serde_json::Map<String, serde_json::Value> =
+            metadata.into_iter().map(|(name, value)| (name.to_string(), json!(value))).collect();
+        let report = json!({
+            "input": source,
+            "pages": count_pages(doc),
+            "version": doc.version,
+            "metadata": metadata,
+            "linearized": is_linearized(doc),
+            "tagged": tagged,
+            "portfolio": is_portfolio(doc),
+            "fonts": fonts.len(),
+            "fonts_without_unicode": without_unicode,
+        });
+        println!("{}", render_json(&report, pretty));
+        return;
+    }
+
+    let yes_no = |value: bool| if value { "yes" } else { "no" };
+    println!("ℹ️ '{}'", source);
+    println!("  Pages: {}", count_pages(doc));
+    println!("  PDF version: {}", doc.version);
+    for (name, value) in &metadata {
+        let mut label = name.to_string();
+        label[..1].make_ascii_uppercase();
+        println!("  {}: {}", label, value);
+    }
+    println!("  Linearized: {}", yes_no(is_linearized(doc)));
+    println!("  Tagged: {}", yes_no(tagged));
+    println!("  Portfolio: {}", yes_no(is_portfolio(doc)));
+    println!("  Fonts: {} ({} without a usable ToUnicode map)", fonts.len(), without_unicode);
+}
+
+/// Extracts and cleans the document as `convert` would, then prints the `stats`
+/// counts of the result along with the quality score of the raw extraction,
+/// as text or as JSON when `json` is set.
+fn print_stats(doc: &Document, source: &str, options: &ProcessingOptions, json: Option<bool>) {
+    let text = extract_document_text(doc).unwrap_or_else(|e| {
+        fail(Failure::Extraction, Some(source), format!("Error extracting text from PDF: {}", e));
+    });
+    let content = clean_extracted_text(&text, options);
+    let characters = content.chars().count();
+    let words = content.split_whitespace().count();
+    let lines = content.lines().filter(|line| !line.trim().is_empty()).count();
+    let score = quality_score(&text);
+
+    if let Some(pretty) = json {
+        let report = json!({
+            "input": source,
+            "pages": count_pages(doc),
+            "characters": characters,
+            "words": words,
+            "lines": lines,
+            "quality_score": score,
+        });
+        println!("{}", render_json(&report, pretty));
+        return;
+    }
+
+    println!("📊 Statistics for '{}'", source);
+    println!("  Pages: {}", count_pages(doc));
+    println!("  Characters: {}", characters);
+    println!("  Words: {}", words);
+    println!("  Lines: {}", lines);
+    println!("  Quality score: {}/100", score);
+}
+
+/// What `--report-fonts` says about one font dictionary.
+#[derive(Debug, PartialEq)]
+struct FontInfo {
+    id: lopdf::ObjectId,
+    name: String,
+    subtype: String,
+    /// The /Encoding name, "custom" for an encoding dictionary, or none.
+    encoding: Option<String>,
+    embedded: bool,
+    /// Subset fonts carry a six-letter tag like `ABCDEF+` before their name.
+    subset: bool,
+    to_unicode: bool,
+}
+
+impl FontInfo {
+    /// Whether text in this font is likely to extract as the wrong characters:
+    /// it has no ToUnicode map and its codes are not standard character codes.
+    /// That is the case for Type0 fonts (glyph IDs through Identity-H and
+    /// similar), Type3 fonts, and embedded simple fonts without one of the
+    /// standard encodings. Unembedded simple fonts and standard-encoded ones
+    /// decode fine without a map.
+    fn lacks_unicode(&self) -> bool {
+        const STANDARD_ENCODINGS: [&str; 3] = ["WinAnsiEncoding", "MacRomanEncoding", "StandardEncoding"];
+        if self.to_unicode {
+            return false;
+        }
+        match self.subtype.as_str() {
+            "Type0" | "Type3" => true,
+            _ => self.embedded && !self.encoding.as_deref().is_some_and(|e| STANDARD_ENCODINGS.contains(&e)),
+        }
+    }
+}
+
+/// Pages that use a font for which `FontInfo::lacks_unicode` holds, with the
+/// names of those fonts. Only fonts in the page's own resources are checked.
+fn missing_tounicode_pages(doc: &Document, fonts: &[FontInfo]) -> Vec<(u32, Vec<String>)> {
+    let fonts: HashMap<lopdf::ObjectId, &FontInfo> = fonts.iter().map(|font| (font.id, font)).collect();
+    let mut pages = Vec::new();
+    for (page, page_id) in doc.get_pages() {
+        let Some(page_fonts) = inherited_page_attribute(doc, page_id, b"Resources")
+            .and_then(|r| r.as_dict().ok())
+            .and_then(|r| r.get_deref(b"Font", doc).and_then(Object::as_dict).ok())
+        else {
+            continue;
+        };
+        let mut names: Vec<String> = page_fonts
+            .iter()
+            .filter_map(|(_, font)| fonts.get(&font.as_reference().ok()?))
+            .filter(|font| font.lacks_unicode())
+            .map(|font| font.name.clone())
+            .collect();
+        names.sort();
+        names.dedup();
+        if !names.is_empty() {
+            pages.push((page, names));
+        }
+    }
+    pages
+}
+
+/// Describes every font dictionary in the document, in object order. The
+/// descendant CIDFonts of Type0 fonts are not listed by themselves; their
+/// font program counts as the Type0 font's. Type3 fonts draw their glyphs
+/// with content streams in the file, so they count as embedded. Fonts written
+/// inline in a resource dictionary instead of as objects are not found.
+fn document_fonts(doc: &Document) -> Vec<FontInfo> {
+    let name = |dict: &Dictionary, key: &[u8]| {
+        dict.get_deref(key, doc)
+            .and_then(Object::as_name)
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .ok()
+    };
+    let has_font_file = |font: &Dictionary| {
+        font.get_deref(b"FontDescriptor", doc)
+            .and_then(Object::as_dict)
+            .is_ok_and(|descriptor| [b"FontFile".as_slice(), b"FontFile2", b"FontFile3"].iter().any(|key| descriptor.has(key)))
+    };
+
+    let mut fonts = Vec::new();
+    for (&id, object) in &doc.objects {
+        let Ok(font) = object.as_dict() else {
+            continue;
+        };
+        let subtype = name(font, b"Subtype").unwrap_or_default();
+        if name(font, b"Type").as_deref() != Some("Font") || subtype.starts_with("CIDFontType") {
+            continue;
+        }
+
+        let descendant = font
+            .get_deref(b"DescendantFonts", doc)
+            .and_then(Object::as_array)
+            .ok()
+            .and_then(|fonts| fonts.first())
+            .and_then(|first| doc.dereference(first).and_then(|(_, f)| f.as_dict()).ok());
+        let base_font = name(font, b"BaseFont").unwrap_or_else(|| "(unnamed)".to_string());
+        let encoding = match font.get_deref(b"Encoding", doc) {
+            Ok(Object::Name(encoding)) => Some(String::from_utf8_lossy(encoding).into_owned()),
+            Ok(_) => Some("custom".to_string()),
+            Err(_) => None,
+        };
+        fonts.push(FontInfo {
+            id,
+            subset: base_font.find('+') == Some(6) && base_font[..6].bytes().all(|b| b.is_ascii_uppercase()),
+            name: base_font,
+            embedded: subtype == "Type3" || has_font_file(font) || descendant.is_some_and(has_font_file),
+            subtype,
+            encoding,
+            to_unicode: font.has(b"ToUnicode"),
+        });
+    }
+    fonts
+}
+
+/// Prints the `--report-fonts` list as text, or as JSON when `json` is set,
+/// followed by the pages whose fonts lack a usable ToUnicode map.
+fn print_fonts(doc: &Document, source: &str, json: Option<bool>) {
+    let fonts = document_fonts(doc);
+    let missing = missing_tounicode_pages(doc, &fonts);
+
+    if let Some(pretty) = json {
+        let fonts: Vec<serde_json::Value> = fonts
+            .iter()
+            .map(|font| {
+                json!({
+                    "object": format!("{} {} R", font.id.0, font.id.1),
+                    "name": font.name,
+                    "subtype": font.subtype,
+                    "encoding": font.encoding,
+                    "embedded": font.embedded,
+                    "subset": font.subset,
+                    "to_unicode": font.to_unicode,
+                })
+            })
+            .collect();
+        let missing: Vec<u32> = missing.iter().map(|(page, _)| *page).collect();
+        let report = json!({ "input": source, "fonts": fonts, "missing_tounicode_pages": missing });
+        println!("{}", render_json(&report, pretty));
+        return;
+    }
+
+    if fonts.is_empty() {
+        println!("No fonts in '{}'", source);
+        return;
+    }
+    println!("Fonts in '{}' ({}):", source, fonts.len());
+    for font in &fonts {
+        let embedded = match (font.embedded, font.subset) {
+            (true, true) => "embedded subset",
+            (true, false) => "embedded",
+            (false, _) => "not embedded",
+        };
+        println!(
+            "  {} — {}, {}, {}, {}",
+            font.name,
+            font.subtype,
+            font.encoding.as_deref().unwrap_or("built-in encoding"),
+            embedded,
+            if font.to_unicode { "ToUnicode" } else { "no ToUnicode" }
+        );
+    }
+    for (page, names) in &missing {
+        println!("⚠️ Page {} uses fonts without a usable ToUnicode map: {}", page, names.join(", "));
+    }
+}
+
+/// What a page holds, as far as deciding whether it needs OCR goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageKind {
+    Text,
+    /// No extractable text, but an image covering most of the page.
+    Scanned,
+    /// Neither text nor a large image.
+    Blank,
+}
+
+/// Pages where one image covers at least this share of the media box count as
+/// scanned when they have no text.
+const SCANNED_IMAGE_COVERAGE: f64 = 0.5;
+
+/// Classifies a page by its extracted text and the largest image drawn on it.
+fn page_kind(doc: &Document, page: u32, page_id: lopdf::ObjectId) -> Result<PageKind, OutputError> {
+    if !extract_pages_text(doc, [page])?.trim().is_empty() {
+        return Ok(PageKind::Text);
+    }
+
+    let page_area = inherited_page_attribute(doc, page_id, b"MediaBox")
+        .and_then(|b| b.as_array().ok())
+        .map(|b| b.iter().filter_map(|n| n.as_float().ok()).collect::<Vec<_>>())
+        .filter(|b| b.len() == 4)
+        .map_or(612.0 * 792.0, |b| ((b[2] - b[0]) * (b[3] - b[1])).abs() as f64);
+    let mut largest = 0.0;
+    if let Ok(content) = doc.get_page_content(page_id) {
+        let resources = inherited_page_attribute(doc, page_id, b"Resources").and_then(|r| r.as_dict().ok());
+        largest_image_area(doc, &content, resources, 1.0, &mut largest, 0);
+    }
+
+    Ok(if largest >= page_area * SCANNED_IMAGE_COVERAGE {
+        PageKind::Scanned
+    } else {
+        PageKind::Blank
+    })
+}
+
+/// Finds the largest area an image XObject covers on the page, descending into
+/// form XObjects. An image fills the unit square of its CTM, so its area is
+/// the determinant of the CTM; only that is tracked, since the determinant of
+/// a product of matrices is the product of their determinants.
+fn largest_image_area(
+    doc: &Document,
+    content: &[u8],
+    resources: Option<&Dictionary>,
+    scale: f64,
+    largest: &mut f64,
+    depth: usize,
+) {
+    let Ok(content) = Content::decode(content) else {
+        return;
+    };
+    let determinant = |operands: &[Object]| {
+        let m: Vec<f64> = operands.iter().filter_map(|n| n.as_float().ok()).map(f64::from).collect();
+        if m.len() >= 4 {
+            (m[0] * m[3] - m[1] * m[2]).abs()
+        } else {
+            1.0
+        }
+    };
+
+    let mut scale = scale;
+    let mut saved = Vec::new();
+    for operation in &content.operations {
+        match operation.operator.as_str() {
+            "q" => saved.push(scale),
+            "Q" => scale = saved.pop().unwrap_or(scale),
+            "cm" => scale *= determinant(&operation.operands),
+            "Do" => {
+                let Some(Ok(Object::Stream(xobject))) = operation.operands.first().and_then(|name| {
+                    let name = name.as_name().ok()?;
+                    let xobjects = resources?.get_deref(b"XObject", doc).and_then(Object::as_dict).ok()?;
+                    Some(xobjects.get_deref(name, doc))
+                }) else {
+                    continue;
+                };
+                match xobject.dict.get(b"Subtype").and_then(Object::as_name) {
+                    Ok(b"Image") => *largest = f64::max(*largest, scale),
+                    Ok(b"Form") if depth < 8 => {
+                        let matrix = xobject.dict.get(b"Matrix").and_then(Object::as_array).map_or(1.0, |m| determinant(m));
+                        let form_resources = xobject.dict.get_deref(b"Resources", doc).and_then(Object::as_dict).ok();
+                        if let Ok(form_content) = xobject.get_plain_content() {
+                            largest_image_area(doc, &form_content, form_resources.or(resources), scale * matrix, largest, depth + 1);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Prints the `--scanned-pages` report as text, or as JSON when `json` is set.
+fn print_page_kinds(doc: &Document, source: &str, json: Option<bool>, warnings: &mut Warnings) {
+    let mut pages: HashMap<&str, Vec<u32>> = HashMap::new();
+    let mut page_count = 0;
+    for (page, page_id) in doc.get_pages() {
+        page_count += 1;
+        let kind = match page_kind(doc, page, page_id) {
+            Ok(PageKind::Text) => "text_pages",
+            Ok(PageKind::Scanned) => "scanned_pages",
+            Ok(PageKind::Blank) => "blank_pages",
+            Err(e) => {
+                warnings.warn(Some(page), "page_failed", format!("could not extract page: {}", e));
+                continue;
+            }
+        };
+        pages.entry(kind).or_default().push(page);
+    }
+    let list = |kind: &str| pages.get(kind).cloned().unwrap_or_default();
+
+    if let Some(pretty) = json {
+        let report = json!({
+            "input": source,
+            "pages": page_count,
+            "text_pages": list("text_pages"),
+            "scanned_pages": list("scanned_pages"),
+            "blank_pages": list("blank_pages"),
+        });
+        println!("{}", render_json(&report, pretty));
+        return;
+    }
+
+    let describe = |pages: Vec<u32>| {
+        if pages.is_empty() {
+            return "0".to_string();
+        }
+        let numbers: Vec<String> = pages.iter().map(u32::to_string).collect();
+        format!("{} ({})", pages.len(), numbers.join(", "))
+    };
+    println!("Page content of '{}' ({} pages):", source, page_count);
+    println!("  Text: {}", describe(list("text_pages")));
+    println!("  Scanned: {}", describe(list("scanned_pages")));
+    println!("  Blank: {}", describe(list("blank_pages")));
+}
+
+/// Lists where the document carries JavaScript actions, without running any of
+/// them. The usual trigger points (OpenAction, additional actions, the
+/// JavaScript name tree and annotation actions) are named; scripts anywhere
+/// else, such as on outline items or form fields, are reported by object number.
+fn scan_javascript(doc: &Document) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut found = Vec::new();
+
+    if let Ok(catalog) = doc.catalog() {
+        if let Ok(action) = catalog.get(b"OpenAction") {
+            javascript_actions(doc, action, "OpenAction", &mut seen, &mut found);
+        }
+        additional_actions(doc, catalog, "document", &mut seen, &mut found);
+
+        let scripts = catalog
+            .get_deref(b"Names", doc)
+            .and_then(Object::as_dict)
+            .and_then(|names| names.get_deref(b"JavaScript", doc))
+            .and_then(Object::as_dict);
+        if let Ok(scripts) = scripts {
+            for (name, action) in name_tree_entries(doc, scripts) {
+                let location = format!("named script '{}'", decode_pdf_string(name));
+                javascript_actions(doc, action, &location, &mut seen, &mut found);
+            }
+        }
+    }
+
+    for (page, page_id) in doc.get_pages() {
+        if let Ok(page_dict) = doc.get_dictionary(page_id) {
+            additional_actions(doc, page_dict, &format!("page {}", page), &mut seen, &mut found);
+        }
+        for annot in doc.get_page_annotations(page_id).unwrap_or_default() {
+            let subtype = annot.get(b"Subtype").and_then(Object::as_name).unwrap_or(b"unknown");
+            let location = format!("page {} {} annotation", page, String::from_utf8_lossy(subtype));
+            if let Ok(action) = annot.get(b"A") {
+                javascript_actions(doc, action, &location, &mut seen, &mut found);
+            }
+            additional_actions(doc, annot, &location, &mut seen, &mut found);
+        }
+    }
+
+    for (id, object) in &doc.objects {
+        let stray = stray_javascript_actions(object, &seen);
+        found.extend((0..stray).map(|_| format!("object {} {}", id.0, id.1)));
+    }
+
+    found
+}
+
+/// Whether `dict` is a JavaScript action.
+fn is_javascript_action(dict: &Dictionary) -> bool {
+    dict.get(b"S").and_then(Object::as_name).is_ok_and(|s| s == b"JavaScript") || dict.has(b"JS")
+}
+
+/// Records `action` and the actions chained after it through /Next. `seen`
+/// holds every action dictionary already visited, by address, so shared or
+/// cyclic chains are only followed once.
+fn javascript_actions<'a>(
+    doc: &'a Document,
+    action: &'a Object,
+    location: &str,
+    seen: &mut HashSet<*const Dictionary>,
+    found: &mut Vec<String>,
+) {
+    let Ok(action) = doc.dereference(action).and_then(|(_, action)| action.as_dict()) else {
+        return;
+    };
+    if !seen.insert(action as *const Dictionary) {
+        return;
+    }
+    if is_javascript_action(action) {
+        found.push(location.to_string());
+    }
+
+    match doc.dereference(action.get(b"Next").unwrap_or(&Object::Null)) {
+        Ok((_, Object::Array(next))) => {
+            for next in next {
+                javascript_actions(doc, next, location, seen, found);
+            }
+        }
+        Ok((_, next)) => javascript_actions(doc, next, location, seen, found),
+        Err(_) => {}
+    }
+}
+
+/// Records the actions in an /AA (additional actions) dictionary, labelled
+/// with their trigger, e.g. "page 2 (/AA /O)".
+fn additional_actions(
+    doc: &Document,
+    dict: &Dictionary,
+    owner: &str,
+    seen: &mut HashSet<*const Dictionary>,
+    found: &mut Vec<String>,
+) {
+    if let Ok(triggers) = dict.get_deref(b"AA", doc).and_then(Object::as_dict) {
+        for (trigger, action) in triggers {
+            let location = format!("{} (/AA /{})", owner, String::from_utf8_lossy(trigger));
+            javascript_actions(doc, action, &location, seen, found);
+        }
+    }
+}
+
+/// Counts JavaScript action dictionaries nested inside `object` that the named
+/// locations did not reach. References are not followed, since every indirect
+/// object is searched on its own.
+fn stray_javascript_actions(object: &Object, seen: &HashSet<*const Dictionary>) -> usize {
+    let dict = match object {
+        Object::Dictionary(dict) => dict,
+        Object::Stream(stream) => &stream.dict,
+        Object::Array(items) => return items.iter().map(|item| stray_javascript_actions(item, seen)).sum(),
+        _ => return 0,
+    };
+
+    let own = usize::from(is_javascript_action(dict) && !seen.contains(&(dict as *const Dictionary)));
+    own + dict.iter().map(|(_, value)| stray_javascript_actions(value, seen)).sum::<usize>()
+}
+
+/// Retries a failed or damaged load for `--repair` with a cross-reference
+/// table rebuilt from the objects found in the file. A load counts as damaged
+/// when objects listed in the table could not be read, which is how lopdf
+/// reports wrong offsets. Encryption errors are returned as they are. Whether
+/// the repair worked is reported as a `repaired` or `repair_failed` warning.
+fn repair_document(
+    loaded: Result<(Document, Unlocked), lopdf::Error>,
+    bytes: impl FnOnce() -> std::io::Result<Vec<u8>>,
+    password: Option<&str>,
+    warnings: &mut Warnings,
+    verbose: bool,
+) -> Result<(Document, Unlocked), lopdf::Error> {
+    match &loaded {
+        Err(lopdf::Error::Decryption(_)) => return loaded,
+        Err(e) if verbose => println!("Loading failed ({}), attempting repair...", e),
+        Err(_) => {}
+        Ok((doc, _)) if !has_unreadable_objects(doc) => return loaded,
+        Ok(_) if verbose => println!("Some objects could not be read, attempting repair..."),
+        Ok(_) => {}
+    }
+    let repaired = bytes().map_err(|e| e.to_string()).and_then(|bytes| {
+        let rebuilt = rebuild_xref(&bytes).ok_or("no objects or document catalog found")?;
+        Document::load_mem(&rebuilt)
+            .and_then(|doc| unlock_document(doc, password))
+            .map_err(|e| e.to_string())
+    });
+    match repaired {
+        Ok(repaired) => {
+            let detail = format!("repaired '{}' by rebuilding its cross-reference table", warnings.source);
+            warnings.warn(None, "repaired", detail);
+            Ok(repaired)
+        }
+        Err(repair_err) => {
+            let detail = format!("could not repair '{}': {}", warnings.source, repair_err);
+            warnings.warn(None, "repair_failed", detail);
+            loaded
+        }
+    }
+}
+
+/// Whether an object the cross-reference table points at is missing from the
+/// loaded document.
+fn has_unreadable_objects(doc: &Document) -> bool {
+    doc.reference_table.entries.iter().any(|(&id, entry)| match *entry {
+        lopdf::xref::XrefEntry::Normal { generation, .. } => !doc.objects.contains_key(&(id, generation)),
+        _ => false,
+    })
+}
+
+/// Appends a fresh cross-reference table to a PDF, built by scanning the file
+/// for `N G obj` headers; a later definition of an object wins, as with an
+/// incremental update. The trailer keeps the last `/Root`, `/Info`,
+/// `/Encrypt` and `/ID` found in the file. Returns `None` when there is no
+/// header, no object or no document catalog.
+fn rebuild_xref(bytes: &[u8]) -> Option<Vec<u8>> {
+    let start = bytes.windows(5).position(|w| w == b"%PDF-")?;
+    let pdf = &bytes[start..];
+    let header = regex::bytes::Regex::new(r"(?m)^[ \t]*(\d+)[ \t\r\n]+(\d+)[ \t\r\n]+obj\b").unwrap();
+    let mut offsets = std::collections::BTreeMap::new();
+    for caps in header.captures_iter(pdf) {
+        let number = |i: usize| std::str::from_utf8(&caps[i]).ok()?.parse::<u32>().ok();
+        if let (Some(id), Some(generation)) = (number(1), number(2)) {
+            offsets.insert(id, (caps.get(1).unwrap().start(), generation));
+        }
+    }
+    let size = offsets.keys().next_back()? + 1;
+
+    let last = |pattern: &str| {
+        let re = regex::bytes::Regex::new(pattern).unwrap();
+        re.find_iter(pdf).last().map(|m| String::from_utf8_lossy(m.as_bytes()).into_owned())
+    };
+    let root = last(r"/Root\s+\d+\s+\d+\s+R").or_else(|| {
+        let catalog = regex::bytes::Regex::new(r"(?s)(\d+)\s+(\d+)\s+obj\s*<<(?:[^>]|>[^>])*/Type\s*/Catalog").unwrap();
+        let caps = catalog.captures_iter(pdf).last()?;
+        Some(format!("/Root {} {} R", String::from_utf8_lossy(&caps[1]), String::from_utf8_lossy(&caps[2])))
+    })?;
+
+    let mut rebuilt = bytes.to_vec();
+    let xref_offset = rebuilt.len() - start + 1;
+    let mut table = format!("\nxref\n0 {}\n0000000000 65535 f \n", size);
+    for id in 1..size {
+        match offsets.get(&id) {
+            Some((offset, generation)) => table.push_str(&format!("{:010} {:05} n \n", offset, generation)),
+            None => table.push_str("0000000000 00000 f \n"),
+        }
+    }
+    let mut trailer = format!("/Size {} {}", size, root);
+    for pattern in [r"/Info\s+\d+\s+\d+\s+R", r"/Encrypt\s+\d+\s+\d+\s+R", r"/ID\s*\[[^\]]*\]"] {
+        if let Some(entry) = last(pattern) {
+            trailer.push(' ');
+            trailer.push_str(&entry);
+        }
+    }
+    table.push_str(&format!("trailer\n<< {} >>\nstartxref\n{}\n%%EOF\n", trailer, xref_offset));
+    rebuilt.extend_from_slice(table.as_bytes());
+    Some(rebuilt)
+}
+
+fn parse_tab_mode(value: &str) -> Result<TabMode, String> {
+    match value {
+        "collapse" => Ok(TabMode::Collapse),
+        "keep" => Ok(TabMode::Keep),
+        "expand" => Ok(TabMode::Expand(4)),
+        _ => match value.strip_prefix("expand=") {
+            Some(width) => width
+                .parse()
+                .map(TabMode::Expand)
+                .map_err(|_| format!("invalid tab width '{}'", width)),
+            None => Err(format!(
+                "unknown tab mode '{}' (expected collapse, keep, or expand[=N])",
+                value
+            )),
+        },
+    }
+}
+
+fn parse_newline(value: &str) -> Result<Newline, String> {
+    match value {
+        "lf" => Ok(Newline::Lf),
+        "crlf" => Ok(Newline::Crlf),
+        "native" => Ok(Newline::Native),
+        _ => Err(format!("unknown newline style '{}' (expected lf, crlf, or native)", value)),
+    }
+}
+
+/// Writes finished output to `path` with the line endings chosen with
+/// `--newline`.
+fn write_output(path: &Path, text: &str, options: &ProcessingOptions) -> std::io::Result<()> {
+    write_file(path, with_line_endings(text, options.newline).as_bytes(), options)?;
+    if options.verify {
+        verify_output(path, options).map_err(|e| verification_error(path, e))?;
+    }
+    Ok(())
+}
+
+/// Writes `data` to `path`, applying `--file-mode` when given, and with
+/// `--verify` reads it back to check all of it arrived.
+fn write_file(path: &Path, data: &[u8], options: &ProcessingOptions) -> std::io::Result<()> {
+    store_file(path, data, options)?;
+    if options.verify {
+        verify_written(path, data).map_err(|e| verification_error(path, e))?;
+    }
+    Ok(())
+}
+
+/// Writes `data` to `path`. With `--file-mode` the file is created with that
+/// mode so it is never readable more widely in between, then set to it
+/// explicitly since the umask may have removed bits.
+fn store_file(path: &Path, data: &[u8], options: &ProcessingOptions) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = options.file_mode {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(mode)
+            .open(path)?;
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+        return file.write_all(data);
+    }
+
+    #[cfg(not(unix))]
+    let _ = options;
+    fs::write(path, data)
+}
+
+fn verification_error(path: &Path, problem: String) -> std::io::Error {
+    std::io::Error::other(format!("verifying '{}' failed: {}", path.display(), problem))
+}
+
+/// Checks that the file at `path` holds exactly `data`.
+fn verify_written(path: &Path, data: &[u8]) -> Result<(), String> {
+    let written = fs::read(path).map_err(|e| format!("could not read it back: {}", e))?;
+    if written.len() != data.len() {
+        return Err(format!("{} bytes were written but {} read back", data.len(), written.len()));
+    }
+    if written != data {
+        return Err("its content differs from what was written".to_string());
+    }
+    Ok(())
+}
+
+/// Checks that a written text output is UTF-8 and starts and ends with the
+/// banner and footer exactly when `--no-header` and `--no-footer` are off.
+fn verify_output(path: &Path, options: &ProcessingOptions) -> Result<(), String> {
+    let written = fs::read(path).map_err(|e| format!("could not read it back: {}", e))?;
+    let text = String::from_utf8(written).map_err(|e| format!("it is not valid UTF-8: {}", e))?;
+    let footer = format!("=== CONTENT ENDS ==={}", with_line_endings("\n", options.newline));
+    let expected = [
+        ("banner", text.starts_with("=== PDF TEXT EXTRACTION ==="), !options.no_header),
+        ("footer", text.ends_with(&footer), !options.no_footer),
+    ];
+    for (part, present, wanted) in expected {
+        if present != wanted {
+            let state = if present { "present" } else { "missing" };
+            return Err(format!("the {} is {} but should {}be there", part, state, if wanted { "" } else { "not " }));
+        }
+    }
+    Ok(())
+}
+
+/// Creates `dir` and any missing parents, setting `--dir-mode` on each
+/// directory created here. Directories that already existed are left as they
+/// are.
+fn create_output_dir(dir: &Path, options: &ProcessingOptions) -> std::io::Result<()> {
+    let missing: Vec<&Path> = dir
+        .ancestors()
+        .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists())
+        .collect();
+    fs::create_dir_all(dir)?;
+
+    #[cfg(unix)]
+    if let Some(mode) = options.dir_mode {
+        use std::os::unix::fs::PermissionsExt;
+
+        for created in missing {
+            fs::set_permissions(created, fs::Permissions::from_mode(mode))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (missing, options);
+
+    Ok(())
+}
+
+/// Parses an octal permission mode such as `640`, `0640` or `0o640`.
+fn parse_mode(value: &str) -> Result<u32, String> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("invalid permission mode '{}' (expected octal, e.g. 640)", value)),
+    }
+}
+
+/// Converts the LF line endings used during processing to `newline`.
+fn with_line_endings(text: &str, newline: Newline) -> std::borrow::Cow<'_, str> {
+    let crlf = match newline {
+        Newline::Lf => false,
+        Newline::Crlf => true,
+        Newline::Native => cfg!(windows),
+    };
+    if crlf {
+        text.replace("\r\n", "\n").replace('\n', "\r\n").into()
+    } else {
+        text.into()
+    }
+}
+
+fn parse_whitespace_mode(value: &str) -> Result<WhitespaceMode, String> {
+    match value {
+        "aggressive" => Ok(WhitespaceMode::Aggressive),
+        "conservative" => Ok(WhitespaceMode::Conservative),
+        "none" => Ok(WhitespaceMode::Preserve),
+        _ => Err(format!(
+            "unknown whitespace level '{}' (expected aggressive, conservative, or none)",
+            value
+        )),
+    }
+}
+
+/// Replaces each run of three or more spaces with a single space, leaving
+/// single and double spaces (and all other whitespace) untouched.
+fn collapse_long_space_runs(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut spaces = 0;
+
+    for c in text.chars() {
+        if c == ' ' {
+            spaces += 1;
+            continue;
+        }
+        result.extend(std::iter::repeat_n(' ', if spaces >= 3 { 1 } else { spaces }));
+        spaces = 0;
+        result.push(c);
+    }
+    result.extend(std::iter::repeat_n(' ', if spaces >= 3 { 1 } else { spaces }));
+
+    result
+}
+
+/// Collapses every run of whitespace into a single space and trims the ends.
+/// Runs containing tabs are instead rendered according to `tabs`.
+fn normalize_whitespace(text: &str, tabs: TabMode) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_run = false;
+    let mut run_tabs = 0;
+
+    for c in text.chars() {
+        if c.is_whitespace() {
+            in_run = true;
+            if c == '\t' {
+                run_tabs += 1;
+            }
+            continue;
+        }
+
+        if in_run && !result.is_empty() {
+            match tabs {
+                TabMode::Keep if run_tabs > 0 => {
+                    result.extend(std::iter::repeat_n('\t', run_tabs));
+                }
+                TabMode::Expand(width) if run_tabs > 0 => {
+                    result.extend(std::iter::repeat_n(' ', run_tabs * width));
+                }
+                _ => result.push(' '),
+            }
+        }
+        in_run = false;
+        run_tabs = 0;
+        result.push(c);
+    }
+
+    result
+}
+
+/// Heuristic 0-100 estimate of how usable the extracted text is, combining the
+/// share of printable characters, the share of word-like tokens, the average
+/// word length and the amount of text. Low scores point at scanned pages or
+/// broken font encodings that probably need OCR or manual review.
+fn quality_score(text: &str) -> u8 {
+    let visible: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if visible.is_empty() {
+        return 0;
+    }
+
+    let printable = visible
+        .iter()
+        .filter(|&&c| !c.is_control() && c != '\u{FFFD}' && !('\u{E000}'..='\u{F8FF}').contains(&c))
+        .count();
+    let printable_ratio = printable as f64 / visible.len() as f64;
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let recognizable = words.iter().filter(|word| looks_like_word(word)).count();
+    let word_ratio = recognizable as f64 / words.len() as f64;
+
+    let average_length = visible.len() as f64 / words.len() as f64;
+    let length_factor = if average_length < 2.0 {
+        average_length / 2.0
+    } else if average_length > 12.0 {
+        12.0 / average_length
+    } else {
+        1.0
+    };
+
+    let volume_factor = (visible.len() as f64 / 200.0).min(1.0);
+
+    let score = 35.0 * printable_ratio + 40.0 * word_ratio + 15.0 * length_factor + 10.0 * volume_factor;
+    score.round().clamp(0.0, 100.0) as u8
+}
+
+/// A token counts as a word if, ignoring surrounding punctuation, it is a number
+/// or made of letters with at least one vowel (any non-ASCII letters are given
+/// the benefit of the doubt).
+fn looks_like_word(token: &str) -> bool {
+    let word = token.trim_matches(|c: char| !c.is_alphanumeric());
+    if word.is_empty() {
+        return false;
+    }
+    if word.chars().all(|c| c.is_ascii_digit()) {
+        return true;
+    }
+
+    word.chars().all(char::is_alphabetic)
+        && (!word.is_ascii() || word.chars().any(|c| "aeiouyAEIOUY".contains(c)))
+}
+
+/// Joins the lines of the raw text into running text, keeping paragraph breaks
+/// as newlines for the whitespace normalization step to deal with.
+fn join_lines(raw_text: &str) -> String {
+    let mut processed = String::new();
+    
+    for line in raw_text.lines() {
+        let trimmed = line.trim();
+        
+        // Skip empty lines but preserve paragraph breaks
+        if trimmed.is_empty() {
+            if !processed.ends_with("\n\n") && !processed.is_empty() {
+                processed.push('\n');
+            }
+            continue;
+        }
+        
+        // Add line with proper spacing
+        if !processed.is_empty() && !processed.ends_with('\n') {
+            // Check if we need a space between words that got split across lines
+            let last_char = processed.chars().last().unwrap_or(' ');
+            
+            if !last_char.is_whitespace() {
+                processed.push(' ');
+            }
+        }
+        
+        processed.push_str(trimmed);
+    }
+    
+    processed
+}
+
+/// Normalizes whitespace within each line but keeps the line boundaries,
+/// squeezing runs of blank lines down to a single one.
+fn normalize_lines(raw_text: &str, tabs: TabMode) -> String {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in raw_text.lines() {
+        let line = normalize_whitespace(line, tabs);
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+
+    lines.join("\n")
+}
+
+fn process_extracted_text(raw_text: &str, options: &ProcessingOptions) -> String {
+    wrap_content(&clean_extracted_text(raw_text, options), options)
+}
+
+/// U+00AD, an invisible marker of where a word may be hyphenated.
+const SOFT_HYPHEN: char = '\u{AD}';
+
+/// Cleans up the raw extraction into the content that goes between the banner
+/// and the footer.
+fn clean_extracted_text(raw_text: &str, options: &ProcessingOptions) -> String {
+    let mut text = raw_text.to_string();
+    if !options.keep_soft_hyphens {
+        // A soft hyphen only marks where a word may break, so dropping it
+        // rejoins the word
+        text = text.replace(SOFT_HYPHEN, "");
+    }
+    if options.normalize_quotes {
+        text = substitute_chars(&text, QUOTE_SUBSTITUTIONS);
+    }
+    if options.normalize_dashes {
+        text = substitute_chars(&text, DASH_SUBSTITUTIONS);
+    }
+    if options.dekern {
+        text = text.lines().map(dekern_line).collect::<Vec<_>>().join("\n");
+    }
+    if options.bidi {
+        text = text.lines().map(reorder_bidi_line).collect::<Vec<_>>().join("\n");
+    }
+    if options.strip_page_numbers {
+        text = strip_page_numbers(&text);
+    }
+    let raw_text = text.as_str();
+
+    let cleaned = match options.whitespace {
+        // Joining lines would leave --grep a single line to match
+        WhitespaceMode::Aggressive if options.collapse_spaces_only || options.grep.is_some() => normalize_lines(raw_text, options.tabs),
+        // Clean up multiple consecutive spaces and normalize whitespace
+        WhitespaceMode::Aggressive => normalize_whitespace(&join_lines(raw_text), options.tabs),
+        WhitespaceMode::Conservative => collapse_long_space_runs(raw_text),
+        WhitespaceMode::Preserve => raw_text.to_string(),
+    };
+    let cleaned = if options.ascii_fold {
+        deunicode::deunicode(&cleaned)
+    } else {
+        cleaned
+    };
+
+    match &options.grep {
+        Some(pattern) => grep_lines(&cleaned, pattern, options.grep_context),
+        None => cleaned,
+    }
+}
+
+/// Keeps the lines matching `pattern` plus `context` lines on either side,
+/// with a `--` line between groups that are not adjacent, like `grep -C`.
+fn grep_lines(text: &str, pattern: &Regex, context: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut keep = vec![false; lines.len()];
+    for (index, line) in lines.iter().enumerate() {
+        if pattern.is_match(line) {
+            let last = (index + context).min(lines.len() - 1);
+            keep[index.saturating_sub(context)..=last].fill(true);
+        }
+    }
+
+    let mut kept: Vec<&str> = Vec::new();
+    let mut previous = None;
+    for (index, line) in lines.iter().enumerate().filter(|(index, _)| keep[*index]) {
+        if previous.is_some_and(|previous| previous + 1 < index) {
+            kept.push("--");
+        }
+        kept.push(line);
+        previous = Some(index);
+    }
+    kept.join("\n")
+}
+
+/// Removes page-number lines. "Page 5", "Page 5 of 20", "5 of 20" and "- 5 -"
+/// are always dropped. A bare number is only dropped when it sits alone
+/// between blank lines and either another such number is one page away from
+/// it or it is the first or last line with text, as the number of a single
+/// page (`--page N`) is. A lone "42" mid-text or a column of figures in a
+/// table is left alone.
+fn strip_page_numbers(text: &str) -> String {
+    let labelled = Regex::new(r"(?i)^(?:(?:page|pg\.?|p\.)\s*(\d+)(?:\s*(?:of|/)\s*(\d+))?|(\d+)\s+of\s+(\d+)|[-\u2013\u2014]\s*(\d+)\s*[-\u2013\u2014])$").unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    let is_blank = |index: Option<usize>| index.and_then(|i| lines.get(i)).is_none_or(|line| line.trim().is_empty());
+
+    let mut bare_numbers = HashMap::new();
+    for (index, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        let is_number = line.len() <= 4 && !line.is_empty() && line.bytes().all(|b| b.is_ascii_digit());
+        if is_number && is_blank(index.checked_sub(1)) && is_blank(Some(index + 1)) {
+            bare_numbers.insert(index, line.parse::<u32>().unwrap());
+        }
+    }
+    let numbers: HashSet<u32> = bare_numbers.values().copied().collect();
+    let first_text = lines.iter().position(|line| !line.trim().is_empty());
+    let last_text = lines.iter().rposition(|line| !line.trim().is_empty());
+
+    let is_page_number = |index: usize, line: &str| {
+        if let Some(captures) = labelled.captures(line.trim()) {
+            // "3 of 2" is not a page position; only check that form, since
+            // "Page 3 of 2" is unambiguous anyhow
+            return match (captures.get(3), captures.get(4)) {
+                (Some(page), Some(total)) => page.as_str().parse::<u64>().ok() <= total.as_str().parse::<u64>().ok(),
+                _ => true,
+            };
+        }
+        let at_edge = Some(index) == first_text || Some(index) == last_text;
+        bare_numbers.get(&index).is_some_and(|&n| {
+            at_edge || numbers.contains(&(n + 1)) || n.checked_sub(1).is_some_and(|p| numbers.contains(&p))
+        })
+    };
+
+    lines
+        .iter()
+        .enumerate()
+        .filter(|&(index, line)| !is_page_number(index, line))
+        .map(|(_, line)| *line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Reorders a line that contains right-to-left characters. PDFs place glyphs
+/// in visual order, so RTL runs come out reversed; the bidi algorithm's
+/// reordering reverses them back, leaving left-to-right text where it is.
+fn reorder_bidi_line(line: &str) -> String {
+    let has_rtl = line
+        .chars()
+        .any(|c| matches!(bidi_class(c), BidiClass::R | BidiClass::AL));
+    if !has_rtl {
+        return line.to_string();
+    }
+
+    let info = BidiInfo::new(line, None);
+    info.paragraphs
+        .iter()
+        .map(|paragraph| info.reorder_line(paragraph, paragraph.range.clone()))
+        .collect()
+}
+
+/// Typographic quotes and the straight quote each one becomes.
+const QUOTE_SUBSTITUTIONS: &[(char, char)] = &[
+    ('\u{2018}', '\''), // ‘ left single quotation mark
+    ('\u{2019}', '\''), // ’ right single quotation mark
+    ('\u{201A}', '\''), // ‚ single low-9 quotation mark
+    ('\u{201B}', '\''), // ‛ single high-reversed-9 quotation mark
+    ('\u{2032}', '\''), // ′ prime
+    ('\u{201C}', '"'),  // “ left double quotation mark
+    ('\u{201D}', '"'),  // ” right double quotation mark
+    ('\u{201E}', '"'),  // „ double low-9 quotation mark
+    ('\u{201F}', '"'),  // ‟ double high-reversed-9 quotation mark
+    ('\u{2033}', '"'),  // ″ double prime
+];
+
+/// Dash-like characters that all become a plain hyphen-minus.
+const DASH_SUBSTITUTIONS: &[(char, char)] = &[
+    ('\u{2010}', '-'), // ‐ hyphen
+    ('\u{2011}', '-'), // ‑ non-breaking hyphen
+    ('\u{2012}', '-'), // ‒ figure dash
+    ('\u{2013}', '-'), // – en dash
+    ('\u{2014}', '-'), // — em dash
+    ('\u{2015}', '-'), // ― horizontal bar
+    ('\u{2212}', '-'), // − minus sign
+];
+
+fn substitute_chars(text: &str, table: &[(char, char)]) -> String {
+    text.chars()
+        .map(|c| table.iter().find(|(from, _)| *from == c).map_or(c, |&(_, to)| to))
+        .collect()
+}
+
+/// Collapses runs of at least three single letters separated by single
+/// spaces, as produced by letter-spaced titles, into words. Wider gaps are
+/// kept as word breaks. Digits are never joined, so table rows such as
+/// "1 2 3" survive, and runs of consecutive letters of one case such as
+/// "a b c" or "A B C" are left alone since they are usually enumerations.
+fn dekern_line(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while !rest.is_empty() {
+        let run_len = single_char_run_len(rest);
+        let run = &rest[..run_len];
+        let letters: Vec<char> = run.chars().filter(|c| *c != ' ').collect();
+
+        if letters.len() >= 3 && !is_enumeration(&letters) {
+            result.extend(letters);
+            rest = &rest[run_len..];
+        } else {
+            // Not a letter-spaced run: copy up to the start of the next token
+            let token_end = rest.find(' ').map_or(rest.len(), |i| i + 1);
+            let next_start = token_end + rest[token_end..].len() - rest[token_end..].trim_start().len();
+            result.push_str(&rest[..next_start]);
+            rest = &rest[next_start..];
+        }
+    }
+
+    result
+}
+
+/// Length in bytes of the leading run of single-letter tokens joined by
+/// exactly one space.
+fn single_char_run_len(text: &str) -> usize {
+    let mut len = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let is_single = c.is_alphabetic() && chars.peek().is_none_or(|&(_, next)| next == ' ');
+        if !is_single {
+            break;
+        }
+        len = i + c.len_utf8();
+
+        // Continue only across a single space followed by another character
+        let mut lookahead = chars.clone();
+        match (lookahead.next(), lookahead.next()) {
+            (Some((_, ' ')), Some((_, next))) if next != ' ' => {
+                chars.next();
+            }
+            _ => break,
+        }
+    }
+
+    len
+}
+
+fn is_enumeration(letters: &[char]) -> bool {
+    (letters.iter().all(char::is_ascii_lowercase) || letters.iter().all(char::is_ascii_uppercase))
+        && letters.windows(2).all(|pair| pair[1] as u32 == pair[0] as u32 + 1)
+}
+
+/// Describes the cleanup steps `options` apply, in the order they run, for the
+/// banner's "Processing:" line.
+fn processing_summary(options: &ProcessingOptions) -> Vec<String> {
+    let mut steps = Vec::new();
+    if options.keep_soft_hyphens {
+        steps.push("soft hyphens kept".to_string());
+    }
+    if options.normalize_quotes {
+        steps.push("quotes straightened".to_string());
+    }
+    if options.normalize_dashes {
+        steps.push("dashes normalized".to_string());
+    }
+    if options.dekern {
+        steps.push("letter-spaced words merged".to_string());
+    }
+    if options.bidi {
+        steps.push("right-to-left text reordered".to_string());
+    }
+    if options.strip_page_numbers {
+        steps.push("page numbers removed".to_string());
+    }
+
+    match options.whitespace {
+        WhitespaceMode::Aggressive if options.collapse_spaces_only || options.grep.is_some() => {
+            steps.push("line breaks kept".to_string());
+            steps.push("whitespace normalized".to_string());
+        }
+        WhitespaceMode::Aggressive => {
+            steps.push("lines joined into paragraphs".to_string());
+            steps.push("whitespace normalized".to_string());
+        }
+        WhitespaceMode::Conservative => steps.push("long space runs collapsed, layout otherwise kept".to_string()),
+        WhitespaceMode::Preserve => steps.push("layout preserved as extracted".to_string()),
+    }
+    if options.whitespace == WhitespaceMode::Aggressive {
+        match options.tabs {
+            TabMode::Collapse => {}
+            TabMode::Expand(width) => steps.push(format!("tabs expanded to {} spaces", width)),
+            TabMode::Keep => steps.push("tabs kept".to_string()),
+        }
+    }
+    if options.ascii_fold {
+        steps.push("transliterated to ASCII".to_string());
+    }
+    steps
+}
+
+/// Surrounds cleaned content with the metadata banner and footer, as enabled.
+fn wrap_content(cleaned: &str, options: &ProcessingOptions) -> String {
+    // Add metadata header for AI context
+    let mut result = String::new();
+    if !options.no_header {
+        result.push_str("=== PDF TEXT EXTRACTION ===\n");
+        result.push_str("This text was extracted from a PDF file for AI processing.\n");
+        result.push_str(&format!("Processing: {}.\n", processing_summary(options).join(", ")));
+        if let Some(provenance) = &options.provenance {
+            result.push_str(&provenance.banner_lines());
+        }
+        if let Some((part, parts)) = options.part {
+            result.push_str(&format!("This file is part {} of {}.\n", part, parts));
+        }
+        if let Some((kept, total)) = options.truncated {
+            result.push_str(&format!("Only the first {} of {} pages were processed.\n", kept, total));
+        }
+        if let Some(pattern) = &options.grep {
+            result.push_str(&format!("Output is filtered to lines matching: {}\n", pattern));
+        }
+        result.push_str("=== CONTENT BEGINS ===\n\n");
+    }
+    result.push_str(cleaned);
+    if options.no_footer {
+        result.push('\n');
+    } else {
+        result.push_str("\n\n=== CONTENT ENDS ===\n");
+    }
+    
+    result
+}
+
+/// Matches a `{{name}}` template placeholder, allowing spaces inside the braces.
+fn template_placeholder() -> Regex {
+    Regex::new(r"\{\{\s*(\w+)\s*\}\}").unwrap()
+}
+
+/// Substitutes the named fields into a `--template-file` template in a single
+/// pass, so placeholders that appear inside the extracted text stay as they
+/// are. Unknown placeholders are left untouched.
+fn render_template(template: &str, fields: &[(&str, &str)]) -> String {
+    template_placeholder()
+        .replace_all(template, |captures: &regex::Captures| {
+            fields
+                .iter()
+                .find(|(name, _)| *name == &captures[1])
+                .map_or_else(|| captures[0].to_string(), |(_, value)| value.to_string())
+        })
+        .into_owned()
+}
+
+/// The /Title entry of the document information dictionary.
+fn document_title(doc: &Document) -> Option<String> {
+    info_entry(doc, b"Title")
+}
+
+/// A text entry of the document information dictionary.
+fn info_entry(doc: &Document, key: &[u8]) -> Option<String> {
+    let info = doc.trailer.get_deref(b"Info", doc).and_then(Object::as_dict).ok()?;
+    info.get_deref(key, doc).and_then(Object::as_str).map(decode_pdf_string).ok()
+}
+
+/// Builds a simple PDF with one page per entry in `pages`, each line of an
+/// entry becoming a line of 12pt Helvetica text. Used as the bundled sample
+/// for `--benchmark`.
+fn build_pdf(pages: &[&str]) -> Document {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let resources_id = doc.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_id },
+    });
+
+    let mut kids = Vec::new();
+    for text in pages {
+        let mut operations = vec![
+            Operation::new("BT", vec![]),
+            Operation::new("Tf", vec!["F1".into(), 12.into()]),
+            Operation::new("Td", vec![72.into(), 720.into()]),
+            Operation::new("TL", vec![14.into()]),
+        ];
+        for line in text.lines() {
+            operations.push(Operation::new("Tj", vec![Object::string_literal(line)]));
+            operations.push(Operation::new("T*", vec![]));
+        }
+        operations.push(Operation::new("ET", vec![]));
+
+        let content = Content { operations };
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+        });
+        kids.push(page_id.into());
+    }
+
+    let count = kids.len() as i64;
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => count,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_pdf(mut doc: Document) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(".pdf").tempfile().unwrap();
+        let mut buffer = Vec::new();
+        doc.save_to(&mut buffer).unwrap();
+        file.write_all(&buffer).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_text_processing() {
+        let raw_text = "This is a test\n   \n\nwith multiple    spaces\nand line breaks";
+        let processed = process_extracted_text(raw_text, &ProcessingOptions::default());
+        
+        assert!(processed.contains("This is a test with multiple spaces and line breaks"));
+        assert!(processed.contains("=== PDF TEXT EXTRACTION ==="));
+    }
+
+    fn with_tabs(tabs: TabMode) -> ProcessingOptions {
+        ProcessingOptions {
+            tabs,
+            ..ProcessingOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_tabs_collapse() {
+        let processed = process_extracted_text("Name\tValue\nfoo\t\tbar", &with_tabs(TabMode::Collapse));
+
+        assert!(processed.contains("Name Value foo bar"));
+    }
+
+    #[test]
+    fn test_tabs_expand() {
+        let processed = process_extracted_text("Name\tValue\nfoo\t\tbar", &with_tabs(TabMode::Expand(4)));
+
+        assert!(processed.contains("Name    Value foo        bar"));
+    }
+
+    #[test]
+    fn test_tabs_keep() {
+        let processed = process_extracted_text("Name\tValue\nfoo \t\tbar", &with_tabs(TabMode::Keep));
+
+        assert!(processed.contains("Name\tValue foo\t\tbar"));
+    }
+
+    #[test]
+    fn test_collapse_spaces_only() {
+        let options = ProcessingOptions {
+            collapse_spaces_only: true,
+            ..ProcessingOptions::default()
+        };
+        let processed = process_extracted_text("  First   line \nsecond\t line\n\n\n\nnext  paragraph\n\n", &options);
+
+        assert!(processed.contains("First line\nsecond line\n\nnext paragraph\n\n=== CONTENT ENDS ==="));
+    }
+
+    #[test]
+    fn test_whitespace_levels() {
+        let raw = "End.  Next   one\tcol\n\n\nlast";
+        let with_level = |whitespace| ProcessingOptions {
+            whitespace,
+            ..ProcessingOptions::default()
+        };
+
+        assert_eq!(clean_extracted_text(raw, &with_level(WhitespaceMode::Aggressive)), "End. Next one col last");
+        assert_eq!(
+            clean_extracted_text(raw, &with_level(WhitespaceMode::Conservative)),
+            "End.  Next one\tcol\n\n\nlast"
+        );
+        assert_eq!(clean_extracted_text(raw, &with_level(WhitespaceMode::Preserve)), raw);
+        assert_eq!(parse_whitespace_mode("none"), Ok(WhitespaceMode::Preserve));
+        assert!(parse_whitespace_mode("light").is_err());
+
+        let check = |line: &str| {
+            let matches = cli().get_matches_from(line.split(' '));
+            check_whitespace_options(matches.subcommand_matches("convert").unwrap())
+        };
+        assert_eq!(check("pdf_bot convert --whitespace none"), Ok(()));
+        assert_eq!(check("pdf_bot convert --tabs keep --collapse-spaces-only"), Ok(()));
+        assert_eq!(
+            check("pdf_bot convert --whitespace conservative --tabs keep"),
+            Err("--tabs has no effect with --whitespace conservative; it only applies to --whitespace aggressive".to_string())
+        );
+        assert!(check("pdf_bot convert --whitespace none --collapse-spaces-only").is_err());
+    }
+
+    #[test]
+    fn test_header_and_footer_flags() {
+        let without_footer = ProcessingOptions {
+            no_footer: true,
+            ..ProcessingOptions::default()
+        };
+        let processed = process_extracted_text("Body text", &without_footer);
+        assert!(processed.starts_with("=== PDF TEXT EXTRACTION ===\n"));
+        assert!(processed.ends_with("Body text\n"));
+        assert!(!processed.contains("=== CONTENT ENDS ==="));
+
+        let without_header = ProcessingOptions {
+            no_header: true,
+            ..ProcessingOptions::default()
+        };
+        let processed = process_extracted_text("Body text", &without_header);
+        assert_eq!(processed, "Body text\n\n=== CONTENT ENDS ===\n");
+    }
+
+    #[test]
+    fn test_dekern_letter_spaced_title() {
+        assert_eq!(dekern_line("T I T L E"), "TITLE");
+        assert_eq!(dekern_line("A N N U A L  R E P O R T 2024"), "ANNUAL  REPORT 2024");
+        assert_eq!(dekern_line("Intro: C H A P T E R one"), "Intro: CHAPTER one");
+    }
+
+    #[test]
+    fn test_dekern_keeps_spaced_single_letters() {
+        assert_eq!(dekern_line("a b test"), "a b test");
+        assert_eq!(dekern_line("choose a b c or d"), "choose a b c or d");
+        assert_eq!(dekern_line("plain words only"), "plain words only");
+    }
+
+    #[test]
+    fn test_dekern_keeps_digit_rows_and_grades() {
+        assert_eq!(dekern_line("1 2 3"), "1 2 3");
+        assert_eq!(dekern_line("0 1 1 0"), "0 1 1 0");
+        assert_eq!(dekern_line("Grades: A B C"), "Grades: A B C");
+        assert_eq!(dekern_line("Q 3 R E S U L T S"), "Q 3 RESULTS");
+    }
+
+    #[test]
+    fn test_normalize_quotes() {
+        let options = ProcessingOptions {
+            normalize_quotes: true,
+            ..ProcessingOptions::default()
+        };
+        let processed = clean_extracted_text("‘single’ and “double” quotes — untouched", &options);
+
+        assert_eq!(processed, "'single' and \"double\" quotes — untouched");
+    }
+
+    #[test]
+    fn test_normalize_dashes() {
+        let options = ProcessingOptions {
+            normalize_dashes: true,
+            ..ProcessingOptions::default()
+        };
+        let processed = clean_extracted_text("pages 3–5 — see “notes”", &options);
+
+        assert_eq!(processed, "pages 3-5 - see “notes”");
+    }
+
+    #[test]
+    fn test_document_fonts() {
+        let mut doc = build_pdf(&["Text"]);
+        let file = doc.add_object(Stream::new(dictionary! {}, vec![0; 4]));
+        let descriptor = doc.add_object(dictionary! { "Type" => "FontDescriptor", "FontFile2" => file });
+        let cid_font = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "CIDFontType2",
+            "BaseFont" => "ABCDEF+NotoSans",
+            "FontDescriptor" => descriptor,
+        });
+        let to_unicode = doc.add_object(Stream::new(dictionary! {}, Vec::new()));
+        doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type0",
+            "BaseFont" => "ABCDEF+NotoSans",
+            "Encoding" => "Identity-H",
+            "DescendantFonts" => vec![cid_font.into()],
+            "ToUnicode" => to_unicode,
+        });
+
+        let fonts = document_fonts(&doc);
+        assert_eq!(fonts.len(), 2);
+        let (helvetica, noto) = (&fonts[0], &fonts[1]);
+        assert_eq!((helvetica.name.as_str(), helvetica.subtype.as_str()), ("Helvetica", "Type1"));
+        assert!(!helvetica.embedded && !helvetica.subset && !helvetica.to_unicode);
+        assert_eq!(helvetica.encoding, None);
+        assert_eq!((noto.subtype.as_str(), noto.encoding.as_deref()), ("Type0", Some("Identity-H")));
+        assert!(noto.embedded && noto.subset && noto.to_unicode);
+        assert!(!helvetica.lacks_unicode() && !noto.lacks_unicode());
+        assert!(FontInfo { to_unicode: false, ..fonts.into_iter().nth(1).unwrap() }.lacks_unicode());
+    }
+
+    #[test]
+    fn test_missing_tounicode_pages() {
+        let mut doc = build_pdf(&["Readable", "Garbled"]);
+        let pages = doc.get_pages();
+        let type3 = doc.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type3", "Encoding" => dictionary! {} });
+        doc.get_dictionary_mut(pages[&2])
+            .unwrap()
+            .set("Resources", dictionary! { "Font" => dictionary! { "F1" => type3 } });
+
+        let fonts = document_fonts(&doc);
+        assert_eq!(missing_tounicode_pages(&doc, &fonts), [(2, vec!["(unnamed)".to_string()])]);
+    }
+
+    #[test]
+    fn test_page_kind() {
+        let mut doc = build_pdf(&["Native text", "", "", ""]);
+        let image = doc.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 1,
+                "Height" => 1,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            },
+            vec![0x80],
+        ));
+        let pages = doc.get_pages();
+        let draw = |size: (i64, i64)| {
+            vec![
+                Operation::new("q", vec![]),
+                Operation::new("cm", vec![size.0.into(), 0.into(), 0.into(), size.1.into(), 0.into(), 0.into()]),
+                Operation::new("Do", vec!["Im1".into()]),
+                Operation::new("Q", vec![]),
+            ]
+        };
+        // A full-page scan, a scan wrapped in a form XObject, and a small logo
+        let form = doc.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Form",
+                "BBox" => vec![0.into(), 0.into(), 1.into(), 1.into()],
+                "Matrix" => vec![612.into(), 0.into(), 0.into(), 792.into(), 0.into(), 0.into()],
+                "Resources" => dictionary! { "XObject" => dictionary! { "Im1" => image } },
+            },
+            Content { operations: draw((1, 1)) }.encode().unwrap(),
+        ));
+        let contents = [
+            draw((612, 792)),
+            vec![Operation::new("Do", vec!["Fm1".into()])],
+            draw((100, 50)),
+        ];
+        for (page, operations) in (2..).zip(contents) {
+            let content_id = doc.add_object(Stream::new(dictionary! {}, Content { operations }.encode().unwrap()));
+            let page = doc.get_dictionary_mut(pages[&page]).unwrap();
+            page.set("Contents", content_id);
+            page.set("Resources", dictionary! { "XObject" => dictionary! { "Im1" => image, "Fm1" => form } });
+        }
+
+        let kinds: Vec<PageKind> = pages.iter().map(|(&page, &id)| page_kind(&doc, page, id).unwrap()).collect();
+        assert_eq!(kinds, [PageKind::Text, PageKind::Scanned, PageKind::Scanned, PageKind::Blank]);
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template = "Summarize {{ source }} ({{pages}} pages):\n<doc>\n{{content}}\n</doc>\n{{unknown}}";
+        let fields = [("content", "Text mentioning {{source}}"), ("source", "report.pdf"), ("pages", "3")];
+        assert_eq!(
+            render_template(template, &fields),
+            "Summarize report.pdf (3 pages):\n<doc>\nText mentioning {{source}}\n</doc>\n{{unknown}}"
+        );
+
+        let mut doc = build_pdf(&["Page"]);
+        assert_eq!(document_title(&doc), None);
+        let info = doc.add_object(dictionary! { "Title" => Object::string_literal("Annual Report") });
+        doc.trailer.set("Info", info);
+        assert_eq!(document_title(&doc).as_deref(), Some("Annual Report"));
+    }
+
+    #[test]
+    fn test_provenance() {
+        let file = write_pdf(build_pdf(&["Hello"]));
+        let bytes = fs::read(file.path()).unwrap();
+        let provenance = Provenance::of_file(file.path()).unwrap();
+        assert_eq!(provenance.size, bytes.len() as u64);
+        // --page drops the banner, and with it the provenance
+        assert!(cli().try_get_matches_from(["pdf_bot", "convert", "-i", "a.pdf", "--provenance", "--page", "2"]).is_err());
+        assert_eq!(provenance.sha256, Provenance::of_bytes(&bytes).sha256);
+        assert!(provenance.modified.is_some());
+        assert_eq!(
+            Provenance::of_bytes(b"abc").sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let options = ProcessingOptions {
+            provenance: Some(Provenance {
+                modified: Some(SystemTime::UNIX_EPOCH),
+                converted: SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(86400),
+                ..Provenance::of_bytes(b"abc")
+            }),
+            ..ProcessingOptions::default()
+        };
+        let output = process_extracted_text("text", &options);
+        assert!(output.contains("Source size: 3 bytes\nSource modified: 1970-01-01T00:00:00Z\nConverted: 1970-01-02T00:00:00Z\n"));
+    }
+
+    #[test]
+    fn test_is_linearized() {
+        let plain = write_pdf(build_pdf(&["Page"]));
+        assert!(!is_linearized(&Document::load(plain.path()).unwrap()));
+
+        // lopdf drops linearization dictionaries when saving, so write the
+        // file by hand; only the parameter dictionary is needed to detect it
+        let objects = [
+            "<</Linearized 1/N 1>>",
+            "<</Type/Catalog/Pages 3 0 R>>",
+            "<</Type/Pages/Kids[4 0 R]/Count 1>>",
+            "<</Type/Page/Parent 3 0 R/MediaBox[0 0 612 792]>>",
+        ];
+        let mut pdf = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::new();
+        for (id, object) in (1..).zip(objects) {
+            offsets.push(pdf.len());
+            pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", id, object));
+        }
+        let xref = pdf.len();
+        pdf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+        for offset in offsets {
+            pdf.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        pdf.push_str(&format!("trailer\n<</Size {}/Root 2 0 R>>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref));
+        let mut linearized = tempfile::NamedTempFile::new().unwrap();
+        linearized.write_all(pdf.as_bytes()).unwrap();
+        assert!(is_linearized(&Document::load(linearized.path()).unwrap()));
+    }
+
+    #[test]
+    fn test_with_default_subcommand() {
+        let args = |line: &str| line.split(' ').map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(with_default_subcommand(args("pdf_bot -i a.pdf")), args("pdf_bot convert -i a.pdf"));
+        assert_eq!(with_default_subcommand(args("pdf_bot -v --stdout -i a.pdf")), args("pdf_bot convert -v --stdout -i a.pdf"));
+        assert_eq!(with_default_subcommand(args("pdf_bot info -i a.pdf")), args("pdf_bot info -i a.pdf"));
+        assert_eq!(with_default_subcommand(args("pdf_bot -v -i stats --format=json stats")), args("pdf_bot -v -i stats --format=json stats"));
+        assert_eq!(with_default_subcommand(args("pdf_bot --help")), args("pdf_bot --help"));
+        assert_eq!(with_default_subcommand(args("pdf_bot completions bash")), args("pdf_bot completions bash"));
+        assert_eq!(with_default_subcommand(args("pdf_bot")), args("pdf_bot convert"));
+        cli().debug_assert();
+
+        let matches = cli().get_matches_from(with_default_subcommand(args("pdf_bot -i a.pdf --dekern --count-pages")));
+        let (name, convert) = matches.subcommand().unwrap();
+        assert_eq!(name, "convert");
+        assert!(convert.get_flag("dekern") && convert.get_flag("count-pages"));
+        assert!(cli().try_get_matches_from(args("pdf_bot info -i a.pdf --dekern")).is_err());
+    }
+
+    #[test]
+    fn test_repair_rebuilds_damaged_xref() {
+        let mut doc = build_pdf(&["Recovered from a broken table"]);
+        doc.reference_table.cross_reference_type = lopdf::xref::XrefType::CrossReferenceTable;
+        let mut buffer = Vec::new();
+        doc.save_to(&mut buffer).unwrap();
+        let offsets = regex::bytes::Regex::new(r"\d{10} (\d{5}) n").unwrap();
+        let damaged = offsets.replace_all(&buffer, &b"0000000009 $1 n"[..]).into_owned();
+
+        let loaded = Document::load_mem(&damaged).and_then(|doc| unlock_document(doc, None));
+        assert!(loaded.as_ref().map_or(true, |(doc, _)| has_unreadable_objects(doc)));
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("warnings.tsv");
+        let mut warnings = Warnings { source: "test".to_string(), log: Some(fs::File::create(&log_path).unwrap()) };
+        let (doc, _) = repair_document(loaded, || Ok(damaged.clone()), None, &mut warnings, false).unwrap();
+        assert!(!has_unreadable_objects(&doc));
+        assert!(extract_document_text(&doc).unwrap().contains("Recovered from a broken table"));
+
+        // A startxref that points nowhere fails the load outright
+        let truncated = regex::bytes::Regex::new(r"startxref\s+\d+").unwrap().replace(&damaged, &b"startxref\n99999999"[..]).into_owned();
+        let loaded = Document::load_mem(&truncated).and_then(|doc| unlock_document(doc, None));
+        assert!(loaded.is_err());
+        let (doc, _) = repair_document(loaded, || Ok(truncated.clone()), None, &mut warnings, false).unwrap();
+        assert!(extract_document_text(&doc).unwrap().contains("Recovered from a broken table"));
+
+        // Without any objects to recover the original error is kept and reported
+        let loaded = Document::load_mem(b"%PDF-1.5\nnot a pdf").and_then(|doc| unlock_document(doc, None));
+        assert!(repair_document(loaded, || Ok(b"%PDF-1.5\nnot a pdf".to_vec()), None, &mut warnings, false).is_err());
+        let log = fs::read_to_string(&log_path).unwrap();
+        let kinds: Vec<&str> = log.lines().map(|line| line.split('\t').nth(2).unwrap()).collect();
+        assert_eq!(kinds, ["repaired", "repaired", "repair_failed"]);
+        assert!(log.contains("could not repair 'test': no objects or document catalog found"));
+    }
+
+    #[test]
+    fn test_extraction_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        let cache = ExtractionCache::new(&cache_dir, "ab12".to_string(), (0, 0, 0));
+        assert_eq!(cache.path, cache_dir.join("ab12.txt"));
+        assert_eq!(cache.load(), None);
+
+        cache.store("raw text\nsecond line", &ProcessingOptions::default()).unwrap();
+        assert_eq!(cache.load().as_deref(), Some("raw text\nsecond line"));
+        let doc = build_pdf(&["Not what the cache says"]);
+        let mut warnings = Warnings { source: "test".to_string(), log: None };
+        let text = extract_with_cache(&doc, false, &cache, &ProcessingOptions::default(), &mut warnings).unwrap();
+        assert_eq!(text, "raw text\nsecond line");
+
+        // A damaged entry is ignored and replaced with a fresh extraction
+        let entry = fs::read_to_string(&cache.path).unwrap();
+        fs::write(&cache.path, entry.replace("second", "altered")).unwrap();
+        assert_eq!(cache.load(), None);
+        let text = extract_with_cache(&doc, false, &cache, &ProcessingOptions::default(), &mut warnings).unwrap();
+        assert!(text.contains("Not what the cache says"));
+        assert_eq!(cache.load().as_deref(), Some(text.as_str()));
+
+        let trimmed = ExtractionCache::new(&cache_dir, "ab12".to_string(), (1, 0, 0));
+        assert_eq!(trimmed.path, cache_dir.join("ab12-skip1-0-max0.txt"));
+        assert_eq!(trimmed.load(), None);
+    }
+
+    #[test]
+    fn test_crop() {
+        assert_eq!(
+            parse_crop("2: 72,600.5,468,120"),
+            Ok(CropRegion { page: Some(2), x: 72.0, y: 600.5, width: 468.0, height: 120.0 })
+        );
+        assert_eq!(parse_crop("0,0,10,10").unwrap().page, None);
+        assert!(parse_crop("0,0,10").is_err());
+        assert!(parse_crop("0:0,0,10,10").is_err());
+        assert!(parse_crop("0,0,-10,10").is_err());
+
+        // Lines at y = 720, 706 and 692
+        let doc = build_pdf(&["Header line\nAbstract text\nFooter line", "Other header\nOther abstract\nOther footer"]);
+        let middle = parse_crop("0,700,612,14").unwrap();
+        assert_eq!(crop_text(&doc, &[middle]).unwrap(), "Abstract text\n\nOther abstract");
+        let first_page_top = parse_crop("1:0,715,612,20").unwrap();
+        assert_eq!(crop_text(&doc, &[middle, first_page_top]).unwrap(), "Header line\n\nOther abstract");
+        assert_eq!(crop_text(&doc, &[first_page_top]).unwrap(), "Header line");
+    }
+
+    #[test]
+    fn test_mark_super_sub() {
+        let mut doc = build_pdf(&[""]);
+        let page_id = doc.get_pages()[&1];
+        // Scripts are drawn smaller and moved with the text rise, as word
+        // processors do
+        let run = |size: i64, rise: i64, text: &str| {
+            vec![
+                Operation::new("Tf", vec!["F1".into(), size.into()]),
+                Operation::new("Ts", vec![rise.into()]),
+                Operation::new("Tj", vec![Object::string_literal(text)]),
+            ]
+        };
+        let operations = [
+            vec![Operation::new("BT", vec![]), Operation::new("Td", vec![72.into(), 720.into()])],
+            run(12, 0, "E = mc"),
+            run(8, 5, "2"),
+            run(12, 0, " and H"),
+            run(8, -3, "2"),
+            run(12, 0, "O, as cited"),
+            run(8, 5, "12"),
+            vec![Operation::new("ET", vec![])],
+        ];
+        let content = Content { operations: operations.concat() }.encode().unwrap();
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+        doc.get_dictionary_mut(page_id).unwrap().set("Contents", content_id);
+
+        assert_eq!(mark_super_sub(&doc).unwrap(), "E = mc^{2} and H_{2}O, as cited^{12}");
+    }
+
+    #[test]
+    fn test_error_json() {
+        assert_eq!(
+            error_json(Failure::Encrypted, Some("a.pdf"), "needs a password").to_string(),
+            r#"{"error":"Encrypted","message":"needs a password","path":"a.pdf"}"#
+        );
+        assert_eq!(error_json(Failure::Usage, None, "no input")["path"], serde_json::Value::Null);
+        assert_eq!(error_json(Failure::TooLarge, None, "Error: too big")["message"], "too big");
+        assert_eq!(Failure::Usage.exit_code(), 2);
+        assert_eq!(Failure::Output.exit_code(), 1);
+
+        let args = |line: &str| line.split(' ').map(OsString::from).collect::<Vec<_>>();
+        assert!(json_errors_requested(&args("pdf_bot convert --error-format json --bogus")));
+        assert!(json_errors_requested(&args("pdf_bot --error-format=json")));
+        assert!(!json_errors_requested(&args("pdf_bot -i json --error-format text")));
+    }
+
+    #[test]
+    fn test_separate_footnotes() {
+        let line = |size: i64, y: i64, text: &str| {
+            vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), size.into()]),
+                Operation::new("Td", vec![72.into(), y.into()]),
+                Operation::new("Tj", vec![Object::string_literal(text)]),
+                Operation::new("ET", vec![]),
+            ]
+        };
+        let mut doc = build_pdf(&["", ""]);
+        let pages = doc.get_pages();
+        let page_contents = [
+            [line(12, 720, "Body text that cites a source.1"), line(12, 706, "The sentence goes on."), line(8, 90, "1 Smith, 2001, p. 4."), line(8, 80, "Reprinted 2010."), line(8, 40, "7")],
+            // Small text in the lower half but without a marker stays put
+            [line(12, 720, "Second page body."), line(8, 90, "Figure credit: archive"), line(12, 60, "Closing body line."), vec![], vec![]],
+        ];
+        for (page, operations) in page_contents.into_iter().enumerate() {
+            let content = Content { operations: operations.concat() }.encode().unwrap();
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+            doc.get_dictionary_mut(pages[&(page as u32 + 1)]).unwrap().set("Contents", content_id);
+        }
+
+        let text = separate_footnotes(&doc, &HashMap::new()).unwrap();
+        let (body, notes) = text.split_once("\n\nFootnotes:").unwrap();
+        assert!(body.contains("The sentence goes on.\n7"));
+        assert!(body.contains("Figure credit: archive") && !body.contains("Smith"));
+        assert_eq!(notes, "\n\n[Page 1] 1 Smith, 2001, p. 4. Reprinted 2010.");
+        let labels = HashMap::from([(1, "xi".to_string())]);
+        assert!(separate_footnotes(&doc, &labels).unwrap().ends_with("\n\n[Page xi] 1 Smith, 2001, p. 4. Reprinted 2010."));
+    }
+
+    #[test]
+    fn test_verify_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let options = ProcessingOptions {
+            verify: true,
+            newline: Newline::Crlf,
+            ..ProcessingOptions::default()
+        };
+        write_output(&path, &process_extracted_text("Checked text", &options), &options).unwrap();
+
+        assert!(verify_written(&path, b"something else").unwrap_err().contains("bytes were written"));
+        let no_header = ProcessingOptions { no_header: true, ..options.clone() };
+        assert!(verify_output(&path, &no_header).unwrap_err().contains("banner is present"));
+        fs::write(&path, b"=== PDF TEXT EXTRACTION ===\n\xff").unwrap();
+        assert!(verify_output(&path, &options).unwrap_err().contains("UTF-8"));
+        fs::write(&path, "=== PDF TEXT EXTRACTION ===\ncut short").unwrap();
+        assert!(verify_output(&path, &options).unwrap_err().contains("footer is missing"));
+    }
+
+    #[test]
+    fn test_soft_hyphens() {
+        let raw = "Inter\u{AD}national co\u{AD}operation";
+        let options = ProcessingOptions::default();
+        assert_eq!(clean_extracted_text(raw, &options), "International cooperation");
+
+        let options = ProcessingOptions { keep_soft_hyphens: true, ..options };
+        assert_eq!(clean_extracted_text(raw, &options), raw);
+        assert!(processing_summary(&options).contains(&"soft hyphens kept".to_string()));
+    }
+
+    #[test]
+    fn test_read_stream() {
+        let file = write_pdf(build_pdf(&["Streamed"]));
+        assert!(!is_stream(file.path()));
+        #[cfg(unix)]
+        assert!(is_stream(Path::new("/dev/null")));
+
+        let size = fs::metadata(file.path()).unwrap().len();
+        let bytes = read_stream(file.path(), Some(size)).unwrap();
+        assert_eq!(bytes.len() as u64, size);
+        assert_eq!(count_pages(&Document::load_mem(&bytes).unwrap()), 1);
+        assert!(read_stream(file.path(), Some(size - 1)).is_err());
+        assert_eq!(read_stream(file.path(), None).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_completions() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut cli(), "pdf_bot", &mut script);
+            let script = String::from_utf8(script).unwrap();
+            for option in ["max-file-bytes", "report-fonts", "whitespace", "input-base64"] {
+                assert!(script.contains(option), "{:?} completion lacks {}", shell, option);
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_by_size() {
+        assert_eq!(split_by_size("one\ntwo\nthree", 7, 1), ["one\ntwo", "three"]);
+        assert_eq!(split_by_size("one\ntwo\nthree", 7, 2), ["one", "two", "three"]);
+        assert_eq!(split_by_size("a long line of words", 10, 1), ["a long", "line of", "words"]);
+        assert_eq!(break_line("unbreakable", 4), ["unbr", "eaka", "ble"]);
+        assert_eq!(break_line("naïve café", 3), ["na", "ïv", "e", "caf", "é"]);
+    }
+
+    #[test]
+    fn test_write_size_limited_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.txt");
+        let content = "Lorem ipsum dolor sit amet.\n".repeat(20);
+        let options = ProcessingOptions {
+            newline: Newline::Crlf,
+            ..ProcessingOptions::default()
+        };
+        let mut overwrite = OverwriteGuard { interactive: false, overwrite_all: false };
+
+        let paths = write_size_limited_parts(content.trim_end(), &output, 400, &options, &mut overwrite).unwrap();
+        assert!(paths.len() > 1);
+        let mut rejoined = Vec::new();
+        for (index, path) in paths.iter().enumerate() {
+            let written = fs::read_to_string(path).unwrap();
+            assert!(written.len() <= 400);
+            assert!(written.contains(&format!("This file is part {} of {}.", index + 1, paths.len())));
+            let body = written.split("=== CONTENT BEGINS ===\r\n\r\n").nth(1).unwrap();
+            rejoined.push(body.split("\r\n\r\n=== CONTENT ENDS ===").next().unwrap().to_string());
+        }
+        assert_eq!(rejoined.join("\r\n"), content.trim_end().replace('\n', "\r\n"));
+
+        assert!(write_size_limited_parts("text", &output, 50, &options, &mut overwrite).is_err());
+    }
+
+    #[test]
+    fn test_keep_pages() {
+        let mut doc = build_pdf(&["Cover", "One", "Two", "Back"]);
+        keep_pages(&mut doc, 2..=3);
+        assert_eq!(count_pages(&doc), 2);
+        let text = extract_document_text(&doc).unwrap();
+        assert!(text.contains("One") && text.contains("Two"));
+        assert!(!text.contains("Cover") && !text.contains("Back"));
+        assert_eq!(extract_single_page(&doc, 1).unwrap().trim(), "One");
+    }
+
+    #[test]
+    fn test_truncate_pages() {
+        let mut doc = build_pdf(&["One", "Two", "Three"]);
+        keep_pages(&mut doc, 1..=2);
+        assert_eq!(count_pages(&doc), 2);
+        let text = extract_document_text(&doc).unwrap();
+        assert!(text.contains("Two"));
+        assert!(!text.contains("Three"));
+
+        let options = ProcessingOptions {
+            truncated: Some((2, 3)),
+            ..ProcessingOptions::default()
+        };
+        assert!(process_extracted_text(&text, &options).contains("Only the first 2 of 3 pages were processed."));
+    }
+
+    #[test]
+    fn test_strip_page_numbers() {
+        assert_eq!(strip_page_numbers("End of chapter.\nPage 5 of 20\nNext chapter"), "End of chapter.\nNext chapter");
+        assert_eq!(strip_page_numbers("First page.\n\n5\n\nSecond page.\n\n6\n"), "First page.\n\n\nSecond page.\n");
+        // A lone bare "5" closing or opening a single page is its number
+        assert_eq!(strip_page_numbers("Only page of the excerpt.\n\n5\n"), "Only page of the excerpt.\n");
+        assert_eq!(strip_page_numbers("5\n\nOnly page of the excerpt."), "\nOnly page of the excerpt.");
+        // A number on its own, or among other figures, is content
+        assert_eq!(strip_page_numbers("The answer is\n\n42\n\nas computed."), "The answer is\n\n42\n\nas computed.");
+        assert_eq!(strip_page_numbers("Count\n41\n42\n43"), "Count\n41\n42\n43");
+        assert_eq!(strip_page_numbers("Score\n3 of 2"), "Score\n3 of 2");
+        assert_eq!(strip_page_numbers("- 7 -\nText"), "Text");
+    }
+
+    #[test]
+    fn test_bidi() {
+        let options = ProcessingOptions {
+            bidi: true,
+            collapse_spaces_only: true,
+            ..ProcessingOptions::default()
+        };
+
+        // "שלום" laid out right to left comes out of the PDF as "םולש"
+        assert_eq!(clean_extracted_text("Hello םולש world\nplain", &options), "Hello שלום world\nplain");
+        assert_eq!(reorder_bidi_line("abc 123"), "abc 123");
+    }
+
+    #[test]
+    fn test_quality_score() {
+        let good = "The quick brown fox jumps over the lazy dog. ".repeat(10);
+        let garbled = "\u{FFFD}\u{FFFD} xkcd#@! \u{E001}\u{E002}\u{E003} qwrtp zzkx \u{1}\u{2}";
+
+        assert!(quality_score(&good) >= 90);
+        assert!(quality_score(garbled) < 50);
+        assert_eq!(quality_score("  \n\t "), 0);
+    }
+
+    #[test]
+    fn test_count_pages() {
+        let file = write_pdf(build_pdf(&["one", "two", "three"]));
+        let (doc, unlocked) = load_document(file.path(), None).unwrap();
+
+        assert_eq!(unlocked, Unlocked::NotEncrypted);
+        assert_eq!(count_pages(&doc), 3);
+    }
+
+    fn write_encrypted_pdf(owner_password: &str, user_password: &str) -> tempfile::NamedTempFile {
+        let mut doc = build_pdf(&["Secret contents"]);
+        doc.trailer.set(
+            "ID",
+            vec![Object::string_literal("0123456789abcdef"), Object::string_literal("0123456789abcdef")],
+        );
+        let version = lopdf::EncryptionVersion::V2 {
+            document: &doc,
+            owner_password,
+            user_password,
+            key_length: 128,
+            permissions: lopdf::Permissions::PRINTABLE,
+        };
+        let state = lopdf::EncryptionState::try_from(version).unwrap();
+        doc.encrypt(&state).unwrap();
+        write_pdf(doc)
+    }
+
+    #[test]
+    fn test_owner_password_only_needs_no_password() {
+        let file = write_encrypted_pdf("owner-secret", "");
+        let (doc, unlocked) = load_document(file.path(), None).unwrap();
+
+        assert_eq!(unlocked, Unlocked::WithoutPassword);
+        assert!(extract_document_text(&doc).unwrap().contains("Secret contents"));
+    }
+
+    #[test]
+    fn test_user_and_owner_passwords() {
+        let file = write_encrypted_pdf("owner-secret", "user-secret");
+
+        assert!(matches!(
+            load_document(file.path(), None),
+            Err(lopdf::Error::Decryption(DecryptionError::IncorrectPassword))
+        ));
+        assert!(load_document(file.path(), Some("wrong")).is_err());
+
+        let (doc, unlocked) = load_document(file.path(), Some("user-secret")).unwrap();
+        assert_eq!(unlocked, Unlocked::UserPassword);
+        assert!(extract_document_text(&doc).unwrap().contains("Secret contents"));
+
+        let (doc, unlocked) = load_document(file.path(), Some("owner-secret")).unwrap();
+        assert_eq!(unlocked, Unlocked::OwnerPassword);
+        assert!(extract_document_text(&doc).unwrap().contains("Secret contents"));
+    }
+
+    #[test]
+    fn test_named_destination() {
+        let mut doc = build_pdf(&["Cover page", "Chapter one", "Chapter two"]);
+        let pages = doc.get_pages();
+        let chapter_one = pages[&2];
+        let chapter_two = pages[&3];
+        let dest = |page: lopdf::ObjectId| Object::Array(vec![page.into(), "Fit".into()]);
+        let tree = doc.add_object(dictionary! {
+            "Names" => vec![
+                Object::string_literal("chapter1"), dest(chapter_one),
+                Object::string_literal("chapter2"), dest(chapter_two),
+            ],
+        });
+        let catalog = doc.catalog_mut().unwrap();
+        catalog.set("Names", dictionary! { "Dests" => tree });
+
+        let from_chapter_one = extract_from_destination(&doc, "chapter1", false).unwrap();
+        assert!(!from_chapter_one.contains("Cover page"));
+        assert!(from_chapter_one.contains("Chapter one"));
+        assert!(from_chapter_one.contains("Chapter two"));
+
+        let only_chapter_one = extract_from_destination(&doc, "chapter1", true).unwrap();
+        assert!(only_chapter_one.contains("Chapter one"));
+        assert!(!only_chapter_one.contains("Chapter two"));
+
+        assert!(extract_from_destination(&doc, "appendix", false).is_err());
+    }
+
+    #[test]
+    fn test_outline_chapters() {
+        let mut doc = build_pdf(&["Preface", "Intro text", "More intro", "Method text"]);
+        let pages = doc.get_pages();
+        let outlines_id = doc.new_object_id();
+        let intro_id = doc.new_object_id();
+        let method_id = doc.new_object_id();
+        doc.objects.insert(
+            intro_id,
+            Object::Dictionary(dictionary! {
+                "Title" => Object::string_literal("Introduction"),
+                "Parent" => outlines_id,
+                "Next" => method_id,
+                "Dest" => vec![pages[&2].into(), "Fit".into()],
+            }),
+        );
+        doc.objects.insert(
+            method_id,
+            Object::Dictionary(dictionary! {
+                "Title" => Object::string_literal("Method"),
+                "Parent" => outlines_id,
+                "Prev" => intro_id,
+                "A" => dictionary! { "S" => "GoTo", "D" => vec![pages[&4].into(), "Fit".into()] },
+            }),
+        );
+        doc.objects.insert(
+            outlines_id,
+            Object::Dictionary(dictionary! { "First" => intro_id, "Last" => method_id }),
+        );
+        doc.catalog_mut().unwrap().set("Outlines", outlines_id);
+
+        assert_eq!(
+            outline_chapters(&doc),
+            vec![("Introduction".to_string(), 2), ("Method".to_string(), 4)]
+        );
+        assert!(outline_chapters(&build_pdf(&["No outline"])).is_empty());
+    }
+
+    #[test]
+    fn test_compare_backends() {
+        let doc = build_pdf(&["Same line\nAnother line", "Second page"]);
+        let comparison = compare_backends(&doc).unwrap();
+        assert_eq!(comparison.lines, 3);
+        assert_eq!(comparison.differing_lines, 0);
+        assert!(comparison.first_difference.is_none());
+        assert!(positional_text(&doc).unwrap().contains("Another line\n\nSecond page"));
+    }
+
+    #[test]
+    fn test_page_labels() {
+        let mut doc = build_pdf(&["Cover", "Preface", "Contents", "Intro", "Body", "Appendix"]);
+        assert!(page_labels(&doc, 0).is_empty());
+
+        let appendix = doc.add_object(dictionary! { "S" => "A", "P" => Object::string_literal("App-") });
+        let kid = doc.add_object(dictionary! {
+            "Nums" => vec![3.into(), dictionary! { "S" => "D", "St" => 1 }.into(), 5.into(), appendix.into()],
+        });
+        let tree = dictionary! {
+            "Nums" => vec![0.into(), dictionary! { "P" => Object::string_literal("Cover") }.into(), 1.into(), dictionary! { "S" => "r" }.into()],
+            "Kids" => vec![kid.into()],
+        };
+        let catalog = doc.trailer.get(b"Root").and_then(Object::as_reference).unwrap();
+        doc.get_dictionary_mut(catalog).unwrap().set("PageLabels", tree);
+
+        let labels = page_labels(&doc, 0);
+        let labels: Vec<&str> = (1..=6).map(|page| labels[&page].as_str()).collect();
+        assert_eq!(labels, ["Cover", "i", "ii", "1", "2", "App-A"]);
+
+        // Skipped cover pages keep their place in the label ranges
+        let matches = cli().get_matches_from(["pdf_bot", "convert", "-i", "a.pdf", "--skip-cover-pages", "3"]);
+        limit_pages(&mut doc, matches.subcommand_matches("convert").unwrap(), "test", false);
+        let labels = page_labels(&doc, 3);
+        let labels: Vec<&str> = (1..=3).map(|page| labels[&page].as_str()).collect();
+        assert_eq!(labels, ["1", "2", "App-A"]);
+        assert_eq!(roman_numeral(1994), "MCMXCIV");
+        assert_eq!(letter_numeral(28), "BB");
+    }
+
+    #[test]
+    fn test_read_annotations() {
+        let mut doc = build_pdf(&["Hello world", "Second page"]);
+        let pages = doc.get_pages();
+        let note = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Text",
+            "Rect" => vec![10.into(), 10.into(), 30.into(), 30.into()],
+            "T" => Object::string_literal("Alice"),
+            "Contents" => Object::string_literal("Check this clause"),
+        });
+        let highlight = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Highlight",
+            "Rect" => vec![70.into(), 715.into(), 105.into(), 735.into()],
+            "QuadPoints" => vec![
+                70.into(), 735.into(), 105.into(), 735.into(),
+                70.into(), 715.into(), 105.into(), 715.into(),
+            ],
+            "Contents" => Object::string_literal("Greeting"),
+        });
+        let link = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Link",
+            "Rect" => vec![0.into(), 0.into(), 10.into(), 10.into()],
+        });
+        doc.get_dictionary_mut(pages[&1])
+            .unwrap()
+            .set("Annots", vec![highlight.into(), link.into()]);
+        doc.get_dictionary_mut(pages[&2]).unwrap().set("Annots", vec![note.into()]);
+
+        let annotations = read_annotations(&doc).unwrap();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].highlighted.as_deref(), Some("Hello"));
+
+        let comments = format_comments(&annotations, &HashMap::new());
+        assert_eq!(
+            comments,
+            "Comments:\n[Page 1] Highlight: \"Hello\" — Greeting\n[Page 2] Note by Alice: Check this clause"
+        );
+
+        let flattened = flatten_annotations(&doc, &annotations).unwrap();
+        assert_eq!(
+            flattened,
+            "Hello world\n[Highlight: \"Hello\" — Greeting]\n\nSecond page\n[Note by Alice: Check this clause]"
+        );
+    }
+
+    #[test]
+    fn test_split_by_pages() {
+        let doc = build_pdf(&["Page A", "Page B", "Page C", "Page D", "Page E"]);
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("book.txt");
+
+        let mut overwrite = OverwriteGuard {
+            interactive: false,
+            overwrite_all: false,
+        };
+
+        let paths = split_by_pages(&doc, &output, 2, &ProcessingOptions::default(), &mut overwrite).unwrap();
+        assert_eq!(paths.len(), 3);
+        assert!(paths[0].ends_with("book_part01.txt"));
+
+        let first = fs::read_to_string(&paths[0]).unwrap();
+        assert!(first.contains("Page A") && first.contains("Page B") && !first.contains("Page C"));
+        let last = fs::read_to_string(&paths[2]).unwrap();
+        assert!(last.contains("Page E") && !last.contains("Page D"));
+    }
+
+    #[test]
+    fn test_scan_javascript() {
+        let mut doc = build_pdf(&["Hello world"]);
+        assert!(scan_javascript(&doc).is_empty());
+
+        let pages = doc.get_pages();
+        let script = |code: &str| dictionary! { "S" => "JavaScript", "JS" => Object::string_literal(code) };
+        let chained = doc.add_object(script("app.alert(2)"));
+        let link = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Link",
+            "Rect" => vec![0.into(), 0.into(), 10.into(), 10.into()],
+            "A" => dictionary! { "S" => "URI", "URI" => Object::string_literal("https://example.com"), "Next" => chained },
+        });
+        doc.get_dictionary_mut(pages[&1]).unwrap().set("Annots", vec![link.into()]);
+        let named = doc.add_object(script("app.alert(3)"));
+        doc.add_object(dictionary! { "Title" => Object::string_literal("Outline item"), "A" => script("app.alert(4)") });
+        let catalog = doc.catalog_mut().unwrap();
+        catalog.set("OpenAction", script("app.alert(1)"));
+        catalog.set(
+            "Names",
+            dictionary! { "JavaScript" => dictionary! { "Names" => vec![Object::string_literal("init"), named.into()] } },
+        );
+
+        let scripts = scan_javascript(&doc);
+        assert_eq!(scripts.len(), 4);
+        assert_eq!(&scripts[..3], ["OpenAction", "named script 'init'", "page 1 Link annotation"]);
+        assert!(scripts[3].starts_with("object "));
+    }
+
+    #[test]
+    fn test_extract_single_page() {
+        let doc = build_pdf(&["Page A", "Page B", "Page C"]);
+
+        let text = extract_single_page(&doc, 2).unwrap();
+        assert!(text.contains("Page B") && !text.contains("Page A") && !text.contains("Page C"));
+        assert!(extract_single_page(&doc, 4).is_err());
+    }
+
+    #[test]
+    fn test_layout_json() {
+        let doc = build_pdf(&["First line\nSecond line"]);
+        let layout = layout_json(&doc, 1).unwrap();
+
+        assert_eq!(layout["page"], 1);
+        let runs = layout["runs"].as_array().unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0]["text"], "First line");
+        assert_eq!(runs[0]["font"], "Helvetica");
+        assert_eq!(runs[0]["size"], 12.0);
+        let bbox: Vec<f64> = runs[1]["bbox"].as_array().unwrap().iter().map(|n| n.as_f64().unwrap()).collect();
+        assert_eq!((bbox[0], bbox[1], bbox[3]), (72.0, 706.0, 718.0));
+        assert!(bbox[2] > bbox[0]);
+
+        assert!(layout_json(&doc, 2).is_err());
+    }
+
+    #[test]
+    fn test_ascii_fold() {
+        let options = ProcessingOptions {
+            ascii_fold: true,
+            ..ProcessingOptions::default()
+        };
+        assert_eq!(
+            clean_extracted_text("Le café où Noël a mangé une crème brûlée à São Paulo, señor Müller.", &options),
+            "Le cafe ou Noel a mange une creme brulee a Sao Paulo, senor Muller."
+        );
+
+        let folded = clean_extracted_text("北京欢迎你 — Straße", &options);
+        assert!(folded.is_ascii());
+        assert!(folded.starts_with("Bei Jing"));
+        assert!(folded.ends_with("Strasse"));
+    }
+
+    #[test]
+    fn test_processing_summary() {
+        let banner = process_extracted_text("text", &ProcessingOptions::default());
+        assert!(banner.contains("Processing: lines joined into paragraphs, whitespace normalized.\n"));
+
+        let options = ProcessingOptions {
+            whitespace: WhitespaceMode::Preserve,
+            normalize_quotes: true,
+            strip_page_numbers: true,
+            ..ProcessingOptions::default()
+        };
+        assert_eq!(
+            processing_summary(&options),
+            ["quotes straightened", "page numbers removed", "layout preserved as extracted"]
+        );
+
+        let options = ProcessingOptions {
+            collapse_spaces_only: true,
+            tabs: TabMode::Expand(4),
+            ..ProcessingOptions::default()
+        };
+        assert_eq!(
+            processing_summary(&options),
+            ["line breaks kept", "whitespace normalized", "tabs expanded to 4 spaces"]
+        );
+    }
+
+    #[test]
+    fn test_layout_csv() {
+        let doc = build_pdf(&["First line\nSecond, \"quoted\" line", "Next page"]);
+        let csv = layout_csv(&doc).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(rows[0], "page,x,y,width,height,text");
+        assert_eq!(rows.len(), 4);
+        assert!(rows[1].starts_with("1,72.00,720.00,"));
+        assert!(rows[1].ends_with(",12.00,First line"));
+        assert!(rows[2].starts_with("1,72.00,706.00,"));
+        assert!(rows[2].ends_with(",\"Second, \"\"quoted\"\" line\""));
+        assert!(rows[3].starts_with("2,72.00,720.00,"));
+    }
+
+    #[test]
+    fn test_layout_json_line_endings() {
+        // --newline only applies to text output, not to JSON written alongside it
+        let doc = build_pdf(&["First line\nSecond line"]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layout.json");
+        let options = ProcessingOptions {
+            newline: Newline::Crlf,
+            ..ProcessingOptions::default()
+        };
+        let layout = render_json(&layout_json(&doc, 1).unwrap(), true) + "\n";
+        write_file(&path, layout.as_bytes(), &options).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(!written.contains('\r'));
+        assert!(!written.contains("\\r"));
+        assert_eq!(lf_line_endings("a\r\nb\rc\n"), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn test_parse_xmp() {
+        let xmp = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about="" xmlns:pdfaid="http://www.aiim.org/pdfa/ns/id/" pdfaid:part="2">
+      <pdfaid:conformance>B</pdfaid:conformance>
+    </rdf:Description>
+    <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/">
+      <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Annual Report</rdf:li></rdf:Alt></dc:title>
+      <dc:creator><rdf:Seq><rdf:li>Alice</rdf:li><rdf:li>Bob</rdf:li></rdf:Seq></dc:creator>
+      <dc:format>application/pdf</dc:format>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+        let info = parse_xmp(xmp).unwrap();
+        assert_eq!(info.pdfa_part.as_deref(), Some("2"));
+        assert_eq!(info.pdfa_conformance.as_deref(), Some("B"));
+        assert_eq!(
+            info.dublin_core,
+            [
+                ("title".to_string(), vec!["Annual Report".to_string()]),
+                ("creator".to_string(), vec!["Alice".to_string(), "Bob".to_string()]),
+                ("format".to_string(), vec!["application/pdf".to_string()]),
+            ]
+        );
+
+        assert!(parse_xmp("<x:xmpmeta>").is_err());
+        let mut doc = build_pdf(&["No metadata"]);
+        assert_eq!(read_xmp(&doc), Ok(None));
+        let metadata = doc.add_object(Stream::new(dictionary! { "Type" => "Metadata" }, xmp.as_bytes().to_vec()));
+        doc.catalog_mut().unwrap().set("Metadata", metadata);
+        assert_eq!(read_xmp(&doc), Ok(Some(info)));
+    }
+
+    #[test]
+    fn test_render_json() {
+        let value = json!({ "page": 1, "runs": [] });
+
+        assert_eq!(render_json(&value, false), r#"{"page":1,"runs":[]}"#);
+        assert_eq!(render_json(&value, true), "{\n  \"page\": 1,\n  \"runs\": []\n}");
+    }
+
+    #[test]
+    fn test_benchmark_timing() {
+        let timing = Timing::from_samples((1..=20).rev().map(f64::from).collect());
+        assert_eq!(timing, Timing { mean: 10.5, median: 10.5, p95: 19.0 });
+        assert_eq!(Timing::from_samples(vec![4.0]).p95, 4.0);
+
+        let doc = build_pdf(&[BENCHMARK_SAMPLE_PAGE; 2]);
+        assert!(extract_document_text(&doc).unwrap().contains("benchmark sample"));
+        let (extraction, processing) = benchmark(&doc, 3, &ProcessingOptions::default()).unwrap();
+        assert!(extraction.p95 >= extraction.median && processing.mean >= 0.0);
+    }
+
+    #[test]
+    fn test_attachments() {
+        let mut doc = build_pdf(&["Report"]);
+        let mut specs = Vec::new();
+        for (key, name, data) in [("a", "data.csv", "x,y\n1,2\n"), ("b", "../data.csv", "other"), ("c", "notes", "n")] {
+            let file = doc.add_object(Stream::new(dictionary! { "Type" => "EmbeddedFile" }, data.as_bytes().to_vec()));
+            let spec = doc.add_object(dictionary! {
+                "Type" => "Filespec",
+                "F" => Object::string_literal(name),
+                "EF" => dictionary! { "F" => file },
+            });
+            specs.push(Object::string_literal(key));
+            specs.push(spec.into());
+        }
+        doc.catalog_mut()
+            .unwrap()
+            .set("Names", dictionary! { "EmbeddedFiles" => dictionary! { "Names" => specs } });
+
+        let attachments = read_attachments(&doc).unwrap();
+        assert_eq!(attachments.len(), 3);
+        assert_eq!(attachments[0].data, b"x,y\n1,2\n");
+
+        let dir = tempfile::tempdir().unwrap();
+        let saved = save_attachments(&attachments, dir.path(), &ProcessingOptions::default()).unwrap();
+        assert_eq!(saved, ["data.csv", "data_2.csv", "notes"]);
+        assert_eq!(fs::read_to_string(dir.path().join("data_2.csv")).unwrap(), "other");
+        assert!(read_attachments(&build_pdf(&["None"])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_expand_portfolio() {
+        let mut doc = build_pdf(&["Cover sheet"]);
+        assert!(!is_portfolio(&doc));
+        let mut specs = Vec::new();
+        let members = [("first.pdf", build_pdf(&["First member"])), ("second.pdf", build_pdf(&["Second member"]))];
+        for (name, mut member) in members {
+            let mut data = Vec::new();
+            member.save_to(&mut data).unwrap();
+            let file = doc.add_object(Stream::new(dictionary! { "Type" => "EmbeddedFile" }, data));
+            let spec = doc.add_object(dictionary! { "Type" => "Filespec", "F" => Object::string_literal(name), "EF" => dictionary! { "F" => file } });
+            specs.push(Object::string_literal(name));
+            specs.push(spec.into());
+        }
+        let readme = doc.add_object(Stream::new(dictionary! { "Type" => "EmbeddedFile" }, b"not a pdf".to_vec()));
+        let readme = doc.add_object(dictionary! { "Type" => "Filespec", "F" => Object::string_literal("readme.txt"), "EF" => dictionary! { "F" => readme } });
+        specs.extend([Object::string_literal("readme.txt"), readme.into()]);
+        let catalog = doc.catalog_mut().unwrap();
+        catalog.set("Names", dictionary! { "EmbeddedFiles" => dictionary! { "Names" => specs } });
+        catalog.set("Collection", dictionary! { "Type" => "Collection", "View" => "D" });
+        assert!(is_portfolio(&doc));
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut overwrite = OverwriteGuard { interactive: false, overwrite_all: false };
+        let mut warnings = Warnings { source: "portfolio.pdf".to_string(), log: None };
+        let written = expand_portfolio(&doc, &dir.path().join("portfolio.txt"), &ProcessingOptions::default(), &mut overwrite, &mut warnings).unwrap();
+
+        let names: Vec<&str> = written.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["first.pdf", "second.pdf"]);
+        assert_eq!(written[1].1, dir.path().join("portfolio_doc02.txt"));
+        assert!(fs::read_to_string(&written[1].1).unwrap().contains("Second member"));
+    }
+
+    #[test]
+    fn test_emphasis_markdown() {
+        let mut doc = build_pdf(&[""]);
+        let page_id = doc.get_pages()[&1];
+        let bold_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "ABCDEF+Helvetica-Bold",
+        });
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+        });
+        // Each line is drawn as (font, text) runs at one size
+        let line = |size: i64, y: i64, runs: &[(&str, &str)]| {
+            let mut operations = vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Td", vec![72.into(), y.into()]),
+            ];
+            for (font, text) in runs {
+                operations.push(Operation::new("Tf", vec![(*font).into(), size.into()]));
+                operations.push(Operation::new("Tj", vec![Object::string_literal(*text)]));
+            }
+            operations.push(Operation::new("ET", vec![]));
+            operations
+        };
+        let mut operations = line(24, 740, &[("F2", "Report Title")]);
+        operations.extend(line(16, 710, &[("F1", "Background")]));
+        operations.extend(line(12, 690, &[("F1", "Plain text with "), ("F2", "bold words"), ("F1", " inside.")]));
+        operations.extend(line(12, 676, &[("F1", "More plain body text.")]));
+        let content = Content { operations }.encode().unwrap();
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+        let page = doc.get_dictionary_mut(page_id).unwrap();
+        page.set("Contents", content_id);
+        page.set("Resources", dictionary! { "Font" => dictionary! { "F1" => font_id, "F2" => bold_id } });
+
+        assert_eq!(
+            emphasis_markdown(&doc).unwrap(),
+            "# Report Title\n### Background\nPlain text with **bold words** inside.\nMore plain body text."
+        );
+        assert!(is_bold_font("Arial-BoldMT"));
+        assert!(!is_bold_font("TimesNewRoman"));
+    }
+
+    #[test]
+    fn test_extract_struct_tree() {
+        let mut doc = build_pdf(&[""]);
+        assert_eq!(extract_struct_tree(&doc).unwrap(), None);
+
+        // The title is drawn last but comes first in the structure tree; the
+        // running header is an artifact outside the tree
+        let page_id = doc.get_pages()[&1];
+        let marked = |tag: &str, mcid: i64, y: i64, text: &str| {
+            vec![
+                Operation::new("BDC", vec![Object::Name(tag.into()), dictionary! { "MCID" => mcid }.into()]),
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                Operation::new("Td", vec![72.into(), y.into()]),
+                Operation::new("Tj", vec![Object::string_literal(text)]),
+                Operation::new("ET", vec![]),
+                Operation::new("EMC", vec![]),
+            ]
+        };
+        let mut operations = vec![
+            Operation::new("BMC", vec!["Artifact".into()]),
+            Operation::new("BT", vec![]),
+            Operation::new("Tf", vec!["F1".into(), 12.into()]),
+            Operation::new("Td", vec![72.into(), 760.into()]),
+            Operation::new("Tj", vec![Object::string_literal("Running header")]),
+            Operation::new("ET", vec![]),
+            Operation::new("EMC", vec![]),
+        ];
+        operations.extend(marked("P", 0, 700, "Body text"));
+        operations.extend(marked("Span", 1, 686, "continues here"));
+        operations.extend(marked("H1", 2, 720, "Title"));
+        let content = Content { operations }.encode().unwrap();
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+        doc.get_dictionary_mut(page_id).unwrap().set("Contents", content_id);
+
+        let heading = dictionary! { "Type" => "StructElem", "S" => "Heading", "Pg" => page_id, "K" => 2 };
+        let span = dictionary! { "Type" => "StructElem", "S" => "Span", "K" => 1 };
+        let paragraph = dictionary! { "Type" => "StructElem", "S" => "P", "Pg" => page_id, "K" => vec![0.into(), span.into()] };
+        let root = doc.add_object(dictionary! {
+            "Type" => "StructTreeRoot",
+            "RoleMap" => dictionary! { "Heading" => "H1" },
+            "K" => dictionary! { "Type" => "StructElem", "S" => "Document", "K" => vec![heading.into(), paragraph.into()] },
+        });
+        doc.catalog_mut().unwrap().set("StructTreeRoot", root);
+
+        assert_eq!(
+            extract_struct_tree(&doc).unwrap().as_deref(),
+            Some("Title\nBody text\ncontinues here")
+        );
+    }
+
+    #[test]
+    fn test_parse_overwrite_reply() {
+        assert_eq!(parse_overwrite_reply("y\n"), Overwrite::Yes);
+        assert_eq!(parse_overwrite_reply("YES"), Overwrite::Yes);
+        assert_eq!(parse_overwrite_reply("a\n"), Overwrite::All);
+        assert_eq!(parse_overwrite_reply("q"), Overwrite::Quit);
+        assert_eq!(parse_overwrite_reply("n"), Overwrite::Skip);
+        assert_eq!(parse_overwrite_reply("\n"), Overwrite::Skip);
+        assert_eq!(parse_overwrite_reply(""), Overwrite::Skip);
+    }
+
+    #[test]
+    fn test_trace_positions() {
+        let doc = build_pdf(&["First line\nSecond line"]);
+        let mut trace = Vec::new();
+        trace_positions(&doc, &mut trace).unwrap();
+
+        let trace = String::from_utf8(trace).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "page 1 x=72.00 y=720.00 size=12.00 \"First line\"");
+        assert!(lines[1].starts_with("page 1 x=72.00 y=706.00 "));
+    }
+
+    #[test]
+    fn test_warn_log_records() {
+        let mut doc = build_pdf(&["Some text", "", "More text"]);
+        let pages = doc.get_pages();
+        doc.get_dictionary_mut(pages[&3]).unwrap().set("Rotate", 90);
+        let log = tempfile::NamedTempFile::new().unwrap();
+
+        let mut warnings = Warnings {
+            source: "doc.pdf".to_string(),
+            log: Some(log.reopen().unwrap()),
+        };
+        check_document(&doc, "text with a \u{7} bell", &mut warnings);
+
+        let records = fs::read_to_string(log.path()).unwrap();
+        assert_eq!(
+            records,
+            "doc.pdf\t\tcontrol_chars\t1 control characters in extracted text\n\
+             doc.pdf\t2\tempty_page\tno text extracted from page\n\
+             doc.pdf\t3\trotated_page\tpage is rotated by 90 degrees\n"
+        );
+    }
+
+    #[test]
+    fn test_numbered_output_path() {
+        let numbered = |path: &str, suffix| numbered_output_path(Path::new(path), suffix);
+
+        assert_eq!(numbered("out/report.txt", "_ch01"), Path::new("out/report_ch01.txt"));
+        assert_eq!(numbered("report", "_ch02"), Path::new("report_ch02"));
+        assert_eq!(numbered("report.txt", ".raw"), Path::new("report.raw.txt"));
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        use base64::Engine;
+
+        let mut bytes = Vec::new();
+        build_pdf(&["Encoded page"]).save_to(&mut bytes).unwrap();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        let wrapped: Vec<&str> = encoded.as_bytes().chunks(76).map(|line| std::str::from_utf8(line).unwrap()).collect();
+
+        let decoded = decode_base64(&wrapped.join("\n")).unwrap();
+        let (doc, _) = unlock_document(Document::load_mem(&decoded).unwrap(), None).unwrap();
+        assert!(extract_document_text(&doc).unwrap().contains("Encoded page"));
+        assert!(decode_base64("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_default_output_path() {
+        assert_eq!(default_output_path(Path::new("docs/My Report.v2.pdf")), Ok(PathBuf::from("My Report.v2.txt")));
+        assert!(default_output_path(Path::new("..")).is_err());
+    }
+
+    #[test]
+    fn test_default_output_path_without_stem() {
+        assert_eq!(default_output_path(Path::new(".pdf")), Ok(PathBuf::from("pdf.txt")));
+        assert_eq!(default_output_path(Path::new("in/foo.")), Ok(PathBuf::from("foo.txt")));
+        assert_eq!(default_output_path(Path::new("...")), Ok(PathBuf::from("output.txt")));
+        assert!(default_output_path(Path::new("docs/")).is_err());
+        assert!(default_output_path(Path::new("/")).is_err());
+        assert!(default_output_path(Path::new("")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let input = Path::new(OsStr::from_bytes(b"in/caf\xe9.pdf"));
+        let output = default_output_path(input).unwrap();
+        assert_eq!(output.as_os_str().as_bytes(), b"caf\xe9.txt");
+        assert_eq!(numbered_output_path(&output, "_ch01").as_os_str().as_bytes(), b"caf\xe9_ch01.txt");
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_pdf(build_pdf(&["Accented name"]));
+        let renamed = dir.path().join(input.file_name().unwrap());
+        fs::copy(file.path(), &renamed).unwrap();
+        let (doc, _) = load_document(&renamed, None).unwrap();
+        assert!(extract_document_text(&doc).unwrap().contains("Accented name"));
+
+        write_output(&dir.path().join(&output), "text", &ProcessingOptions::default()).unwrap();
+        assert!(dir.path().join(&output).exists());
+    }
+
+    #[test]
+    fn test_write_output_crlf() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let mut options = ProcessingOptions {
+            newline: Newline::Crlf,
+            ..ProcessingOptions::default()
+        };
+
+        write_output(&path, &process_extracted_text("First line\nSecond line", &options), &options).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.contains("\r\n"));
+        assert!(!written.replace("\r\n", "").contains('\n'));
+
+        options.newline = Newline::Lf;
+        write_output(&path, "a\nb\n", &options).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "a\nb\n");
+        assert_eq!(parse_newline("native"), Ok(Newline::Native));
+        assert!(parse_newline("cr").is_err());
+    }
+
+    #[test]
+    fn test_grep_lines() {
+        let text = "alpha\nbeta\ngamma\ndelta\nepsilon\nzeta\neta";
+        let pattern = Regex::new("^(beta|zeta)$").unwrap();
+
+        assert_eq!(grep_lines(text, &pattern, 0), "beta\n--\nzeta");
+        assert_eq!(grep_lines(text, &pattern, 1), "alpha\nbeta\ngamma\n--\nepsilon\nzeta\neta");
+        assert_eq!(grep_lines(text, &pattern, 2), text);
+        assert_eq!(grep_lines(text, &Regex::new("omega").unwrap(), 1), "");
+
+        let options = ProcessingOptions {
+            grep: Some(Regex::new("total").unwrap()),
+            ..ProcessingOptions::default()
+        };
+        let processed = process_extracted_text("Items: 3\nThe  total is 42\nThanks", &options);
+        assert!(processed.contains("Output is filtered to lines matching: total\n"));
+        assert!(processed.contains("The total is 42") && !processed.contains("Thanks"));
+        let conservative = ProcessingOptions {
+            whitespace: WhitespaceMode::Conservative,
+            ..options
+        };
+        assert_eq!(clean_extracted_text("Items: 3\nThe  total is 42\nThanks", &conservative), "The  total is 42");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_and_dir_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("private/attachments");
+        let options = ProcessingOptions {
+            file_mode: Some(0o600),
+            dir_mode: Some(0o700),
+            ..ProcessingOptions::default()
+        };
+        create_output_dir(&nested, &options).unwrap();
+        write_file(&nested.join("out.txt"), b"secret", &options).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(&dir.path().join("private")), 0o700);
+        assert_eq!(mode(&nested), 0o700);
+        assert_eq!(mode(&nested.join("out.txt")), 0o600);
+        assert_eq!(parse_mode("0o640"), Ok(0o640));
+        assert!(parse_mode("999").is_err());
+    }
+
+    #[test]
+    fn test_parse_tab_mode() {
+        assert_eq!(parse_tab_mode("expand"), Ok(TabMode::Expand(4)));
+        assert_eq!(parse_tab_mode("expand=8"), Ok(TabMode::Expand(8)));
+        assert!(parse_tab_mode("expand=x").is_err());
+        assert!(parse_tab_mode("spaces").is_err());
     }
 }
\ No newline at end of file